@@ -0,0 +1,93 @@
+use std;
+
+/// Error loading or saving a configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read or written.
+    Io {
+        path: std::path::PathBuf,
+        error: std::io::Error,
+    },
+    /// The file is not valid YAML, TOML or JSON, or does not describe a configuration.
+    /// Lines and columns start at 1.
+    Parse {
+        path: std::path::PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    /// The file was written for a newer version of eriksync.
+    UnsupportedVersion {
+        path: std::path::PathBuf,
+        version: u32,
+    },
+    /// The format cannot be told from the file extension.
+    UnknownFormat(std::path::PathBuf),
+    /// The files parse but do not make a valid configuration together.
+    Validation(String),
+}
+
+impl ConfigError {
+    pub fn io(path: &std::path::Path, error: std::io::Error) -> ConfigError {
+        ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+
+    pub fn parse(
+        path: &std::path::Path,
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    ) -> ConfigError {
+        ConfigError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ConfigError::Io { ref path, ref error } => write!(f, "{}: {}", path.display(), error),
+            ConfigError::Parse {
+                ref path,
+                line,
+                column,
+                ref message,
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                    if let Some(column) = column {
+                        write!(f, ":{}", column)?;
+                    }
+                }
+                write!(f, ": {}", message)
+            }
+            ConfigError::UnsupportedVersion { ref path, version } => {
+                write!(
+                    f,
+                    "{}: configuration version {} is newer than version {} supported by this eriksync, please upgrade eriksync",
+                    path.display(),
+                    version,
+                    super::CONFIG_VERSION
+                )
+            }
+            ConfigError::UnknownFormat(ref path) => {
+                write!(
+                    f,
+                    "{}: unknown format, expected a .yaml, .yml, .toml or .json file",
+                    path.display()
+                )
+            }
+            ConfigError::Validation(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
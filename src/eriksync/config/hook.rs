@@ -0,0 +1,73 @@
+extern crate serde;
+extern crate serde_json;
+
+use schemars;
+use super::Direction;
+
+/// A shell command run before or after a target is synchronized.
+#[derive(Eq, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct Hook {
+    pub command: String,
+
+    /// Run the command on the node over SSH instead of on the local machine.
+    #[serde(default, skip_serializing_if = "super::is_false")]
+    pub remote: bool,
+}
+
+#[allow(dead_code)]
+impl Hook {
+    pub fn new(command: String) -> Hook {
+        Hook {
+            command,
+            remote: false,
+        }
+    }
+
+    pub fn remote(mut self, remote: bool) -> Hook {
+        self.remote = remote;
+        self
+    }
+}
+
+#[derive(Eq, PartialEq, Serialize, Deserialize, JsonSchema, Clone, Debug, Default)]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_push: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_push: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_pull: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_pull: Vec<Hook>,
+}
+
+#[allow(dead_code)]
+impl Hooks {
+    pub fn new() -> Hooks {
+        Hooks::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pre_push.is_empty() && self.post_push.is_empty() && self.pre_pull.is_empty() &&
+            self.post_pull.is_empty()
+    }
+
+    /// Hooks run before synchronizing in the given direction.
+    pub fn pre(&self, direction: Direction) -> &[Hook] {
+        match direction {
+            Direction::Push => &self.pre_push,
+            Direction::Pull => &self.pre_pull,
+        }
+    }
+
+    /// Hooks run after synchronizing in the given direction.
+    pub fn post(&self, direction: Direction) -> &[Hook] {
+        match direction {
+            Direction::Push => &self.post_push,
+            Direction::Pull => &self.post_pull,
+        }
+    }
+}
@@ -1,5 +1,8 @@
+pub mod error;
+pub mod hook;
 pub mod node;
 pub mod target;
+pub mod validation;
 
 extern crate serde;
 extern crate serde_json;
@@ -10,9 +13,14 @@ use std;
 use std::io::prelude::*;
 use std::vec::Vec;
 use std::collections::HashMap;
+use schemars;
+use utils;
 
-pub use self::node::Node;
-pub use self::target::Target;
+pub use self::error::ConfigError;
+pub use self::hook::{Hook, Hooks};
+pub use self::node::{Node, NodeKind};
+pub use self::target::{Target, TargetKind, SyncMode};
+pub use self::validation::{Issue, Severity};
 
 #[derive(Debug, Clone)]
 pub enum ConfigFormat {
@@ -48,50 +56,275 @@ impl ConfigFormat {
     }
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Push,
+    Pull,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Direction::Push => write!(f, "push"),
+            Direction::Pull => write!(f, "pull"),
+        }
+    }
+}
+
+/// Used to leave `false` flags out of saved files.
+pub(super) fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Expand the variables of a configured path: `${VAR}` from the environment and the
+/// built-in `{node}`, `{target}`, `{local_hostname}` and `{date}`. Other names in
+/// braces are left alone.
+pub fn expand_variables(
+    value: &str,
+    node_name: &str,
+    target_name: Option<&str>,
+) -> std::result::Result<String, String> {
+    utils::expand_template(value, |name| match name {
+        "node" => Ok(Some(node_name.to_string())),
+        "target" => {
+            target_name.map(|t| Some(t.to_string())).ok_or_else(|| {
+                String::from("{target} is only defined in target paths")
+            })
+        }
+        "local_hostname" => {
+            utils::local_hostname().map(Some).ok_or_else(|| {
+                String::from("cannot determine {local_hostname}")
+            })
+        }
+        "date" => Ok(Some(utils::today())),
+        _ => Ok(None),
+    })
+}
+
+/// Expands the paths of a node or target, naming the entry in errors.
+pub(super) struct PathExpander<'a> {
+    entry: String,
+    node_name: &'a str,
+    target_name: Option<&'a str>,
+}
+
+impl<'a> PathExpander<'a> {
+    pub(super) fn new(
+        entry: String,
+        node_name: &'a str,
+        target_name: Option<&'a str>,
+    ) -> PathExpander<'a> {
+        PathExpander {
+            entry,
+            node_name,
+            target_name,
+        }
+    }
+
+    pub(super) fn expand(&self, value: &str) -> std::result::Result<String, String> {
+        expand_variables(value, self.node_name, self.target_name).map_err(|e| {
+            format!("{}: {}", self.entry, e)
+        })
+    }
+
+    pub(super) fn expand_option(
+        &self,
+        value: &Option<String>,
+    ) -> std::result::Result<Option<String>, String> {
+        match *value {
+            Some(ref value) => self.expand(value).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Config {
+    /// Version of the layout of the file, missing in files older than versioning.
+    #[serde(default)]
+    pub version: u32,
+
+    /// Further configuration files or globs merged into this one, relative to this file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Patterns excluded from every target.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>,
+
+    /// Hooks run around every target.
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+
     #[serde(default)]
     pub nodes: HashMap<String, Node>,
 
     #[serde(default)]
     pub targets: HashMap<String, Target>,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub groups: HashMap<String, Vec<String>>,
+
+    /// File every node, target and group was loaded from, keyed by `node <name>`,
+    /// `target <name>` and `group <name>`.
+    #[serde(skip)]
+    sources: HashMap<String, std::path::PathBuf>,
+
+    /// The main file as loaded, without anything merged from other files. This is
+    /// what gets saved.
+    #[serde(skip)]
+    main: Option<(std::path::PathBuf, Box<Config>)>,
+
+    /// Every file merged into the configuration with the version it was written in.
+    #[serde(skip)]
+    files: Vec<(std::path::PathBuf, u32)>,
 }
 
+/// Name of the directory next to the main configuration file whose files are merged into it.
+pub const DROP_IN_DIR: &str = "eriksync.d";
+
+/// Version of the configuration layout this build reads and writes.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrades of the configuration layout, applied to a parsed file before it is
+/// read into a `Config`. Entry `i` upgrades version `i` to `i + 1`; files without
+/// a `version` key are version 0.
+const MIGRATIONS: &[fn(&mut serde_json::Value)] = &[migrate_v0_to_v1];
+
+/// Version 1 only adds the `version` key to the layout used before it.
+fn migrate_v0_to_v1(_config: &mut serde_json::Value) {}
+
 #[allow(dead_code)]
 impl Config {
     pub fn new() -> Self {
         Config {
+            version: CONFIG_VERSION,
+            include: Vec::new(),
+            excludes: Vec::new(),
+            hooks: Hooks::new(),
             nodes: HashMap::new(),
             targets: HashMap::new(),
+            groups: HashMap::new(),
+            sources: HashMap::new(),
+            main: None,
+            files: Vec::new(),
         }
     }
 
-    pub fn load_file(path: &std::path::Path) -> std::result::Result<Self, String> {
+    /// Load a configuration file together with the files it includes and the files
+    /// in the `eriksync.d` directory next to it.
+    ///
+    /// Files are merged in this order: the main file, the files it includes in the
+    /// order listed (glob matches sorted by name), then the files of `eriksync.d`
+    /// sorted by name. Included files may include further files, which are merged
+    /// right after the file including them. Every file is merged at most once.
+    /// Excludes and hooks are appended in merge order, while a node, target or
+    /// group defined in more than one file is an error.
+    pub fn load_file(path: &std::path::Path) -> std::result::Result<Self, ConfigError> {
+        let main = Self::read_file(path)?;
 
-        let mut file = match std::fs::File::open(path) {
-            Ok(file) => file,
-            Err(e) => {
-                return Err(e.to_string());
+        let mut ret = Config::new();
+        let mut loaded = Vec::new();
+        ret.merge_file(path, main.clone(), &mut loaded)?;
+
+        let drop_in_dir = path.parent()
+            .unwrap_or_else(|| std::path::Path::new(""))
+            .join(DROP_IN_DIR);
+        if drop_in_dir.is_dir() {
+            let entries = std::fs::read_dir(&drop_in_dir).map_err(|e| {
+                ConfigError::io(&drop_in_dir, e)
+            })?;
+            let mut files: Vec<std::path::PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && ConfigFormat::from_path(p).is_some())
+                .collect();
+            files.sort();
+            for file in files {
+                let config = Self::read_file(&file)?;
+                ret.merge_file(&file, config, &mut loaded)?;
             }
+        }
+
+        ret.include = main.include.clone();
+        ret.main = Some((path.to_path_buf(), Box::new(main)));
+        Ok(ret)
+    }
+
+    /// Read a single configuration file, ignoring its includes.
+    fn read_file(path: &std::path::Path) -> std::result::Result<Self, ConfigError> {
+        let format = match ConfigFormat::from_path(path) {
+            Some(format) => format,
+            None => return Err(ConfigError::UnknownFormat(path.to_path_buf())),
         };
 
-        let config: Config = match ConfigFormat::from_path(&path) {
-            Some(ConfigFormat::yaml) => serde_yaml::from_reader(file).map_err(|e| e.to_string()),
-            Some(ConfigFormat::json) => serde_json::from_reader(file).map_err(|e| e.to_string()),
-            Some(ConfigFormat::toml) => {
-                let mut buf = String::new();
-                match file.read_to_string(&mut buf) {
-                    Ok(_) => {}
-                    Err(e) => return Err(e.to_string()),
+        let mut buf = String::new();
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut buf))
+            .map_err(|e| ConfigError::io(path, e))?;
+
+        // Read the file loosely first to learn its version.
+        let mut value: serde_json::Value = match format {
+            ConfigFormat::yaml => {
+                serde_yaml::from_str(&buf).map_err(|e| yaml_error(path, e))?
+            }
+            ConfigFormat::json => {
+                serde_json::from_str(&buf).map_err(|e| json_error(path, e))?
+            }
+            ConfigFormat::toml => toml::from_str(&buf).map_err(|e| toml_error(path, e))?,
+        };
+        let version = match value.get("version") {
+            None => 0,
+            Some(version) => {
+                match version.as_u64() {
+                    Some(version) if version <= u32::MAX as u64 => version as u32,
+                    _ => {
+                        return Err(ConfigError::Validation(format!(
+                            "{}: version must be a non-negative integer",
+                            path.display()
+                        )))
+                    }
                 }
+            }
+        };
+        if version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion {
+                path: path.to_path_buf(),
+                version,
+            });
+        }
+
+        // Reading the text itself gives errors with their location in the file.
+        let read_text = || -> std::result::Result<Config, ConfigError> {
+            match format {
+                ConfigFormat::yaml => {
+                    serde_yaml::from_str(&buf).map_err(|e| yaml_error(path, e))
+                }
+                ConfigFormat::json => {
+                    serde_json::from_str(&buf).map_err(|e| json_error(path, e))
+                }
+                ConfigFormat::toml => toml::from_str(&buf).map_err(|e| toml_error(path, e)),
+            }
+        };
 
-                toml::from_str(&mut buf).map_err(|e| e.to_string())
+        let config: Config = if version == CONFIG_VERSION {
+            read_text()?
+        } else {
+            for migration in &MIGRATIONS[version as usize..] {
+                migration(&mut value);
             }
-            None => {
-                return Err(format!("Failed to detect format from {:?} ", path));
+            if let Some(object) = value.as_object_mut() {
+                object.insert(String::from("version"), serde_json::Value::from(CONFIG_VERSION));
             }
-        }.unwrap();
+            match serde_json::from_value(value) {
+                Ok(config) => config,
+                Err(e) => {
+                    read_text()?;
+                    return Err(json_error(path, e));
+                }
+            }
+        };
 
         let mut ret = Config::new();
 
@@ -105,22 +338,178 @@ impl Config {
             ret.add_target(target);
         }
 
+        ret.version = version;
+        ret.include = config.include;
+        ret.excludes = config.excludes;
+        ret.hooks = config.hooks;
+        ret.groups = config.groups;
+
         Ok(ret)
     }
 
-    pub fn save_file(&self, path: &std::path::Path, format: ConfigFormat) -> std::io::Result<()> {
-        match std::fs::File::create(path) {
-            Err(e) => Err(e),
-            Ok(mut file) => {
+    /// Merge a file read from `path` and, recursively, the files it includes.
+    fn merge_file(
+        &mut self,
+        path: &std::path::Path,
+        config: Config,
+        loaded: &mut Vec<std::path::PathBuf>,
+    ) -> std::result::Result<(), ConfigError> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if loaded.contains(&canonical) {
+            return Ok(());
+        }
+        loaded.push(canonical);
+        self.files.push((path.to_path_buf(), config.version));
+
+        let base = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+        let includes: Vec<std::path::PathBuf> = config
+            .include
+            .iter()
+            .map(|include| base.join(utils::expand_user(std::path::Path::new(include))))
+            .collect();
+
+        self.merge(path, config)?;
+
+        for include in includes {
+            let include_str = include.to_str().unwrap_or_default();
+            let files = if utils::has_wildcards(include_str) {
+                utils::glob(&include)
+            } else if include.is_file() {
+                vec![include.clone()]
+            } else {
+                return Err(ConfigError::Validation(format!(
+                    "File {:?} included from {:?} does not exist",
+                    include,
+                    path
+                )));
+            };
+            for file in files.into_iter().filter(|f| f.is_file()) {
+                let included = Self::read_file(&file)?;
+                self.merge_file(&file, included, loaded)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge the content of a single file, refusing names already defined elsewhere.
+    fn merge(
+        &mut self,
+        path: &std::path::Path,
+        config: Config,
+    ) -> std::result::Result<(), ConfigError> {
+        let mut claim = |kind: &str, name: &str| {
+            let key = format!("{} {}", kind, name);
+            if let Some(source) = self.sources.get(&key) {
+                return Err(ConfigError::Validation(format!(
+                    "{}{} {} is defined in both {:?} and {:?}",
+                    kind[..1].to_uppercase(),
+                    &kind[1..],
+                    name,
+                    source,
+                    path
+                )));
+            }
+            self.sources.insert(key, path.to_path_buf());
+            Ok(())
+        };
+
+        for name in config.nodes.keys() {
+            claim("node", name)?;
+        }
+        for name in config.targets.keys() {
+            claim("target", name)?;
+        }
+        for name in config.groups.keys() {
+            claim("group", name)?;
+        }
+
+        self.nodes.extend(config.nodes);
+        self.targets.extend(config.targets);
+        self.groups.extend(config.groups);
+        self.excludes.extend(config.excludes);
+        self.hooks.pre_push.extend(config.hooks.pre_push);
+        self.hooks.post_push.extend(config.hooks.post_push);
+        self.hooks.pre_pull.extend(config.hooks.pre_pull);
+        self.hooks.post_pull.extend(config.hooks.post_pull);
+        Ok(())
+    }
+
+    /// Files of the configuration written in an older version than `CONFIG_VERSION`,
+    /// with that version.
+    pub fn outdated_files(&self) -> Vec<(&std::path::Path, u32)> {
+        self.files
+            .iter()
+            .filter(|&&(_, version)| version < CONFIG_VERSION)
+            .map(|(path, version)| (path.as_path(), *version))
+            .collect()
+    }
+
+    /// Rewrite a single configuration file in the current version, keeping a copy of
+    /// the original next to it. Returns the path of the copy, or `None` when the
+    /// file is already current.
+    pub fn migrate_file(
+        path: &std::path::Path,
+    ) -> std::result::Result<Option<std::path::PathBuf>, ConfigError> {
+        let config = Self::read_file(path)?;
+        if config.version == CONFIG_VERSION {
+            return Ok(None);
+        }
+        let format = ConfigFormat::from_path(path).unwrap_or(ConfigFormat::yaml);
+        config.save_file(path, format)
+    }
+
+    /// Check the configuration for entries that cannot work or are likely mistakes.
+    pub fn validate(&self) -> Vec<Issue> {
+        validation::validate(self)
+    }
+
+    /// File other than the main file that a node, target or group of the given kind
+    /// (`node`, `target` or `group`) was loaded from. Such entries cannot be changed
+    /// by saving the main file.
+    pub fn included_from(&self, kind: &str, name: &str) -> Option<&std::path::Path> {
+        let source = self.sources.get(&format!("{} {}", kind, name))?;
+        match self.main {
+            Some((ref main, _)) if main == source => None,
+            _ => Some(source),
+        }
+    }
+
+    /// Save the main file. Entries merged from other files are left out.
+    ///
+    /// A main file written in an older version is saved in the current version,
+    /// keeping a copy of the original next to it as `<file>.v<version>.bak`.
+    /// Returns the path of the copy, if one was made.
+    pub fn save_file(
+        &self,
+        path: &std::path::Path,
+        format: ConfigFormat,
+    ) -> std::result::Result<Option<std::path::PathBuf>, ConfigError> {
+        let mut config = self.main.as_ref().map_or(self, |(_, main)| main).clone();
+        let mut backup = None;
+        if config.version < CONFIG_VERSION {
+            if path.exists() {
+                backup = Some(backup_file(path, config.version)?);
+            }
+            config.version = CONFIG_VERSION;
+        }
+        std::fs::File::create(path)
+            .and_then(|mut file| {
                 file.write_all(
                     match format {
-                        ConfigFormat::json => self.to_json_string(),
-                        ConfigFormat::yaml => self.to_yaml_string(),
-                        ConfigFormat::toml => self.to_toml_string(),
+                        ConfigFormat::json => config.to_json_string(),
+                        ConfigFormat::yaml => config.to_yaml_string(),
+                        ConfigFormat::toml => config.to_toml_string(),
                     }.as_bytes(),
                 )
-            }
-        }
+            })
+            .map_err(|e| ConfigError::io(path, e))?;
+        Ok(backup)
+    }
+
+    /// JSON Schema of a configuration file, generated from the types it is read into.
+    /// It describes YAML and TOML files as well, as they share the same structure.
+    pub fn json_schema() -> String {
+        serde_json::to_string_pretty(&schema_for!(Config)).unwrap_or_default()
     }
 
     pub fn to_json_string(&self) -> String {
@@ -136,6 +525,9 @@ impl Config {
     }
 
     pub fn add_node(&mut self, node: Node) {
+        if let Some((_, ref mut main)) = self.main {
+            main.add_node(node.clone());
+        }
         self.nodes.insert(node.name.to_owned(), node);
     }
 
@@ -146,6 +538,9 @@ impl Config {
     }
 
     pub fn add_target(&mut self, target: Target) {
+        if let Some((_, ref mut main)) = self.main {
+            main.add_target(target.clone());
+        }
         self.targets.insert(target.name.to_owned(), target);
     }
 
@@ -156,6 +551,9 @@ impl Config {
     }
 
     pub fn remove_node(&mut self, node_name: String) {
+        if let Some((_, ref mut main)) = self.main {
+            main.remove_node(node_name.clone());
+        }
         self.nodes.remove(&node_name);
     }
 
@@ -166,6 +564,9 @@ impl Config {
     }
 
     pub fn remove_target(&mut self, target_name: String) {
+        if let Some((_, ref mut main)) = self.main {
+            main.remove_target(target_name.clone());
+        }
         self.targets.remove(&target_name);
     }
 
@@ -175,6 +576,20 @@ impl Config {
         }
     }
 
+    pub fn add_group(&mut self, group_name: String, node_names: Vec<String>) {
+        if let Some((_, ref mut main)) = self.main {
+            main.add_group(group_name.clone(), node_names.clone());
+        }
+        self.groups.insert(group_name, node_names);
+    }
+
+    pub fn remove_group(&mut self, group_name: String) {
+        if let Some((_, ref mut main)) = self.main {
+            main.remove_group(group_name.clone());
+        }
+        self.groups.remove(&group_name);
+    }
+
     pub fn nodes(&self) -> Vec<Node> {
         let mut nodes: Vec<Node> = self.nodes.iter().map(|(_, v)| v.clone()).collect();
         nodes.sort();
@@ -199,6 +614,29 @@ impl Config {
         names
     }
 
+    pub fn group_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.groups.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn get_group(&self, group_name: &String) -> Option<&Vec<String>> {
+        self.groups.get(group_name)
+    }
+
+    /// Node names addressed by a node name, a group name or `all`.
+    pub fn resolve_nodes(&self, name: &String) -> std::result::Result<Vec<String>, String> {
+        if self.contains_node(name) {
+            Ok(vec![name.clone()])
+        } else if let Some(members) = self.get_group(name) {
+            Ok(members.clone())
+        } else if name.to_lowercase() == "all" {
+            Ok(self.node_names())
+        } else {
+            Err(format!("No such node or group: {}", name))
+        }
+    }
+
     pub fn get_node(&self, node_name: &String) -> Option<&Node> {
         self.nodes.get(node_name)
     }
@@ -215,3 +653,89 @@ impl Config {
         self.targets.get(target_name).is_some()
     }
 }
+
+/// Copy a file written in an older version of the configuration to
+/// `<file>.v<version>.bak`, or `<file>.v<version>.bak.<n>` if that exists.
+fn backup_file(
+    path: &std::path::Path,
+    version: u32,
+) -> std::result::Result<std::path::PathBuf, ConfigError> {
+    let mut backup = std::path::PathBuf::from(format!("{}.v{}.bak", path.display(), version));
+    let mut n = 1;
+    while backup.exists() {
+        backup = std::path::PathBuf::from(format!("{}.v{}.bak.{}", path.display(), version, n));
+        n += 1;
+    }
+    std::fs::copy(path, &backup).map_err(|e| ConfigError::io(&backup, e))?;
+    Ok(backup)
+}
+
+/// Parse error of a YAML file, located from the end of its message.
+fn yaml_error(path: &std::path::Path, error: serde_yaml::Error) -> ConfigError {
+    // serde_yaml 0.7 has no accessor for the location, so take it from the
+    // ` at line L column C` suffix of the message.
+    let message = error.to_string();
+    if let Some(i) = message.rfind(" at line ") {
+        let mut location = message[i + " at line ".len()..].split(" column ");
+        let line = location.next().and_then(|l| l.trim().parse().ok());
+        let column = location.next().and_then(|c| c.trim().parse().ok());
+        if line.is_some() {
+            return ConfigError::parse(path, message[..i].to_string(), line, column);
+        }
+    }
+    ConfigError::parse(path, message, None, None)
+}
+
+/// Parse error of a JSON file, located with the 1-based position the parser reports.
+fn json_error(path: &std::path::Path, error: serde_json::Error) -> ConfigError {
+    // Errors that do not come from the text, such as those of `from_value`, are at line 0.
+    if error.line() == 0 {
+        return ConfigError::parse(path, error.to_string(), None, None);
+    }
+    let suffix = format!(" at line {} column {}", error.line(), error.column());
+    let message = error.to_string();
+    let message = message.strip_suffix(suffix.as_str()).unwrap_or(&message).to_string();
+    ConfigError::parse(path, message, Some(error.line()), Some(error.column()))
+}
+
+/// Parse error of a TOML file, located with the 0-based position the parser reports.
+fn toml_error(path: &std::path::Path, error: toml::de::Error) -> ConfigError {
+    match error.line_col() {
+        Some((line, column)) => {
+            let suffix = format!(" at line {}", line + 1);
+            let message = error.to_string();
+            let message = message.strip_suffix(suffix.as_str()).unwrap_or(&message).to_string();
+            ConfigError::parse(path, message, Some(line + 1), Some(column + 1))
+        }
+        None => ConfigError::parse(path, error.to_string(), None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+    use super::{Config, ConfigFormat, CONFIG_VERSION};
+
+    #[test]
+    fn save_file_backs_up_unversioned_main_file() {
+        let dir = std::env::temp_dir().join(format!("eriksync-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("old.yaml");
+        let original = "nodes:\n  nas:\n    description: storage\n";
+        std::fs::write(&path, original).unwrap();
+
+        let mut config = Config::load_file(&path).unwrap();
+        assert_eq!(config.outdated_files(), vec![(path.as_path(), 0)]);
+        config.add_group(String::from("home"), vec![String::from("nas")]);
+        let backup = config.save_file(&path, ConfigFormat::yaml).unwrap();
+
+        let expected = dir.join("old.yaml.v0.bak");
+        assert_eq!(backup.as_ref(), Some(&expected));
+        assert_eq!(std::fs::read_to_string(&expected).unwrap(), original);
+        let saved = Config::load_file(&path).unwrap();
+        assert_eq!(saved.version, CONFIG_VERSION);
+        assert_eq!(saved.get_group(&String::from("home")), Some(&vec![String::from("nas")]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,23 +2,130 @@ extern crate serde;
 extern crate serde_json;
 
 use std::cmp::Ordering;
+use std;
+use std::collections::BTreeMap;
+use schemars;
+use utils;
+use super::{PathExpander, Target};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    /// Reached with rsync over SSH.
+    #[default]
+    Ssh,
+    /// A mounted filesystem such as an external drive or a network share.
+    Local,
+    /// An rsync daemon serving modules over `rsync://`.
+    Daemon,
+}
+
+impl std::fmt::Display for NodeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            NodeKind::Ssh => write!(f, "ssh"),
+            NodeKind::Local => write!(f, "local"),
+            NodeKind::Daemon => write!(f, "daemon"),
+        }
+    }
+}
+
+impl NodeKind {
+    pub fn from_str(s: &str) -> Option<NodeKind> {
+        match s.to_lowercase().as_str() {
+            "ssh" => Some(NodeKind::Ssh),
+            "local" => Some(NodeKind::Local),
+            "daemon" => Some(NodeKind::Daemon),
+            _ => None,
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == NodeKind::default()
+    }
+}
 
 #[allow(dead_code)]
-#[derive(Eq, Serialize, Deserialize, Clone, Debug)]
+#[derive(Eq, Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Node {
     #[serde(skip)]
     pub name: String,
 
     #[serde(default)]
     pub description: String,
+
+    #[serde(default, skip_serializing_if = "NodeKind::is_default")]
+    pub kind: NodeKind,
+
+    /// Refuse to push to the node.
+    #[serde(default, skip_serializing_if = "super::is_false")]
+    pub read_only: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proxy_jump: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_program: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ssh_options: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsync_path: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_root: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount_point: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<String>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub path_rewrites: BTreeMap<String, String>,
 }
 
 #[allow(dead_code)]
 impl Node {
     pub fn new(name: String) -> Node {
         Node {
-            name: name,
+            name,
             description: String::new(),
+            kind: NodeKind::default(),
+            read_only: false,
+            host: None,
+            user: None,
+            port: None,
+            identity_file: None,
+            proxy_jump: Vec::new(),
+            ssh_program: None,
+            ssh_options: Vec::new(),
+            rsync_path: None,
+            targets: Vec::new(),
+            remote_root: None,
+            mount_point: None,
+            module: None,
+            password_file: None,
+            path_rewrites: BTreeMap::new(),
         }
     }
 
@@ -26,6 +133,200 @@ impl Node {
         self.description = description;
         self
     }
+
+    pub fn kind(mut self, kind: NodeKind) -> Node {
+        self.kind = kind;
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Node {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn host(mut self, host: String) -> Node {
+        self.host = Some(host);
+        self
+    }
+
+    pub fn user(mut self, user: String) -> Node {
+        self.user = Some(user);
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Node {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn identity_file(mut self, identity_file: String) -> Node {
+        self.identity_file = Some(identity_file);
+        self
+    }
+
+    pub fn proxy_jump(mut self, jump_host: String) -> Node {
+        self.proxy_jump.push(jump_host);
+        self
+    }
+
+    pub fn ssh_program(mut self, ssh_program: String) -> Node {
+        self.ssh_program = Some(ssh_program);
+        self
+    }
+
+    /// Add an ssh `-o` option in `Key=Value` form.
+    pub fn ssh_option(mut self, option: String) -> Node {
+        self.ssh_options.push(option);
+        self
+    }
+
+    /// Path of the rsync binary on the node.
+    pub fn rsync_path(mut self, rsync_path: String) -> Node {
+        self.rsync_path = Some(rsync_path);
+        self
+    }
+
+    /// Add a target name, pattern or `@tag` to the targets synchronized with the node.
+    /// Entries starting with `!` exclude matching targets.
+    pub fn target(mut self, target: String) -> Node {
+        self.targets.push(target);
+        self
+    }
+
+    /// Whether a target may be synchronized with the node. Nodes without a
+    /// target list accept every target.
+    pub fn allows_target(&self, target: &Target) -> bool {
+        let matches = |pattern: &str| match pattern.strip_prefix('@') {
+            Some(tag) => target.has_tag(tag),
+            None => utils::wildcard_match(pattern, &target.name),
+        };
+
+        let mut included = self.targets.iter().all(|t| t.starts_with('!'));
+        for pattern in &self.targets {
+            if let Some(excluded) = pattern.strip_prefix('!') {
+                if matches(excluded) {
+                    return false;
+                }
+            } else if matches(pattern) {
+                included = true;
+            }
+        }
+        included
+    }
+
+    pub fn remote_root(mut self, remote_root: String) -> Node {
+        self.remote_root = Some(remote_root);
+        self
+    }
+
+    pub fn mount_point(mut self, mount_point: String) -> Node {
+        self.mount_point = Some(mount_point);
+        self
+    }
+
+    pub fn module(mut self, module: String) -> Node {
+        self.module = Some(module);
+        self
+    }
+
+    pub fn password_file(mut self, password_file: String) -> Node {
+        self.password_file = Some(password_file);
+        self
+    }
+
+    /// Rewrite paths starting with `from` to start with `to` on this node.
+    pub fn path_rewrite(mut self, from: String, to: String) -> Node {
+        self.path_rewrites.insert(from, to);
+        self
+    }
+
+    /// Map a target path to where it lives on this node.
+    ///
+    /// The longest matching entry of `path_rewrites` wins. Otherwise home-relative
+    /// and relative paths are placed under `remote_root` when it is set.
+    pub fn remote_path(&self, path: &str) -> String {
+        self.rewrite_path(path).map_or_else(
+            || path.to_string(),
+            |(path, _)| path,
+        )
+    }
+
+    /// Rewritten path and a description of the rule that rewrote it, or `None` when
+    /// the path is used on the node as is.
+    pub fn rewrite_path(&self, path: &str) -> Option<(String, String)> {
+        let rewrite = self.path_rewrites
+            .iter()
+            .filter_map(|(from, to)| {
+                strip_path_prefix(path, from).map(|rest| (from, to, rest))
+            })
+            .max_by_key(|&(from, _, _)| from.len());
+        if let Some((from, to, rest)) = rewrite {
+            return Some((join_path(to, rest), format!("path_rewrites[{}]", from)));
+        }
+
+        if let Some(ref root) = self.remote_root {
+            if let Some(rest) = strip_path_prefix(path, "~") {
+                return Some((join_path(root, rest), String::from("remote_root")));
+            }
+            if !path.starts_with('/') {
+                return Some((join_path(root, path), String::from("remote_root")));
+            }
+        }
+
+        None
+    }
+
+    /// Copy of the node with the variables of its paths expanded.
+    pub fn expanded(&self) -> Result<Node, String> {
+        let expander = PathExpander::new(format!("Node {}", self.name), &self.name, None);
+
+        let mut node = self.clone();
+        node.identity_file = expander.expand_option(&self.identity_file)?;
+        node.remote_root = expander.expand_option(&self.remote_root)?;
+        node.mount_point = expander.expand_option(&self.mount_point)?;
+        node.password_file = expander.expand_option(&self.password_file)?;
+        node.path_rewrites = BTreeMap::new();
+        for (from, to) in &self.path_rewrites {
+            node.path_rewrites.insert(expander.expand(from)?, expander.expand(to)?);
+        }
+        Ok(node)
+    }
+
+    /// Host name used to reach the node, falling back to the node name.
+    pub fn hostname(&self) -> &str {
+        self.host.as_ref().unwrap_or(&self.name)
+    }
+
+    /// SSH destination of the node in `[user@]host` form.
+    pub fn destination(&self) -> String {
+        match self.user {
+            Some(ref user) => format!("{}@{}", user, self.hostname()),
+            None => self.hostname().to_string(),
+        }
+    }
+}
+
+fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let prefix = if prefix.len() > 1 {
+        prefix.trim_end_matches('/')
+    } else {
+        prefix
+    };
+    if path == prefix {
+        return Some("");
+    }
+    if prefix == "/" {
+        return path.strip_prefix('/');
+    }
+    path.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('/'))
+}
+
+fn join_path(base: &str, rest: &str) -> String {
+    if rest.is_empty() {
+        base.to_string()
+    } else {
+        format!("{}/{}", base.trim_end_matches('/'), rest)
+    }
 }
 
 impl PartialOrd for Node {
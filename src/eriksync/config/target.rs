@@ -1,26 +1,329 @@
 extern crate serde;
 extern crate serde_json;
 
+use std;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use schemars;
+use utils;
+use super::{Direction, Hooks, PathExpander};
+
+/// Whether a target is a directory or a single file.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    Dir,
+    File,
+}
+
+/// How the destination of a target is brought up to date.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Make the destination an exact copy, deleting files missing from the source.
+    #[default]
+    Mirror,
+    /// Mirror, but skip files that are newer on the destination.
+    Update,
+    /// Copy new and changed files, never delete.
+    Additive,
+    /// Mirror, but leave files that already exist on the destination untouched.
+    #[serde(rename = "ignore-existing")]
+    IgnoreExisting,
+}
+
+impl std::fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            SyncMode::Mirror => write!(f, "mirror"),
+            SyncMode::Update => write!(f, "update"),
+            SyncMode::Additive => write!(f, "additive"),
+            SyncMode::IgnoreExisting => write!(f, "ignore-existing"),
+        }
+    }
+}
+
+impl SyncMode {
+    pub fn from_str(s: &str) -> Option<SyncMode> {
+        match s.to_lowercase().as_str() {
+            "mirror" => Some(SyncMode::Mirror),
+            "update" => Some(SyncMode::Update),
+            "additive" => Some(SyncMode::Additive),
+            "ignore-existing" => Some(SyncMode::IgnoreExisting),
+            _ => None,
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == SyncMode::default()
+    }
+}
 
 #[allow(dead_code)]
-#[derive(Eq, Serialize, Deserialize, Clone, Debug)]
+#[derive(Eq, Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Target {
     #[serde(skip)]
     pub name: String,
 
     #[serde(default)]
     pub path: String,
+
+    /// Detected from the local path when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<TargetKind>,
+
+    /// Path on the local machine when it differs from `path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_path: Option<String>,
+
+    /// Path on the nodes when it differs from `path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_path: Option<String>,
+
+    /// Files, directories or wildcards below `path` synchronized together, in
+    /// place of the whole of `path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "SyncMode::is_default")]
+    pub mode: SyncMode,
+
+    /// Mode used when pushing, overriding `mode`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub push_mode: Option<SyncMode>,
+
+    /// Mode used when pulling, overriding `mode`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull_mode: Option<SyncMode>,
+
+    /// Directions the target may be synchronized in, both when empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_directions: Vec<Direction>,
+
+    /// Run rsync through sudo on the node and keep ownership of the files.
+    #[serde(default, skip_serializing_if = "super::is_false")]
+    pub privileged: bool,
+
+    /// Also run the local rsync through sudo.
+    #[serde(default, skip_serializing_if = "super::is_false")]
+    pub local_sudo: bool,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Raw rsync filter rules such as `- *.tmp` or `P .git/`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filter: Vec<String>,
+
+    /// Path on specific nodes, overriding `remote_path` and `path` there.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_paths: BTreeMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
 }
 
 #[allow(dead_code)]
 impl Target {
     pub fn new(name: String, path: String) -> Self {
         Target {
-            name: name,
-            path: path,
+            name,
+            path,
+            kind: None,
+            local_path: None,
+            remote_path: None,
+            paths: Vec::new(),
+            tags: Vec::new(),
+            mode: SyncMode::default(),
+            push_mode: None,
+            pull_mode: None,
+            allowed_directions: Vec::new(),
+            privileged: false,
+            local_sudo: false,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            filter: Vec::new(),
+            node_paths: BTreeMap::new(),
+            hooks: Hooks::new(),
+        }
+    }
+
+    pub fn exclude(mut self, pattern: String) -> Self {
+        self.exclude.push(pattern);
+        self
+    }
+
+    pub fn include(mut self, pattern: String) -> Self {
+        self.include.push(pattern);
+        self
+    }
+
+    pub fn filter(mut self, rule: String) -> Self {
+        self.filter.push(rule);
+        self
+    }
+
+    pub fn kind(mut self, kind: TargetKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Kind of the target, detected from the local path unless set explicitly.
+    /// Targets that do not exist locally are taken for directories.
+    pub fn get_kind(&self) -> TargetKind {
+        if let Some(kind) = self.kind {
+            return kind;
+        }
+        // A trailing slash makes the check fail for files, so look at the path without it.
+        let local_path = self.get_local_path();
+        let trimmed = local_path.trim_end_matches('/');
+        let trimmed = if trimmed.is_empty() { local_path } else { trimmed };
+        let local_path = utils::expand_user(std::path::Path::new(trimmed));
+        if self.paths.is_empty() && local_path.is_file() {
+            TargetKind::File
+        } else {
+            TargetKind::Dir
+        }
+    }
+
+    /// Spell a local path or remote location of the target the way rsync has to get it:
+    /// directories end with a slash so their contents are synchronized rather than the
+    /// directory being nested in the destination, files never do.
+    pub fn rsync_path(&self, path: &str) -> String {
+        let trimmed = path.trim_end_matches('/');
+        match self.get_kind() {
+            TargetKind::Dir => format!("{}/", trimmed),
+            TargetKind::File if trimmed.is_empty() => String::from("/"),
+            TargetKind::File => trimmed.to_string(),
+        }
+    }
+
+    pub fn local_path(mut self, local_path: String) -> Self {
+        self.local_path = Some(local_path);
+        self
+    }
+
+    pub fn remote_path(mut self, remote_path: String) -> Self {
+        self.remote_path = Some(remote_path);
+        self
+    }
+
+    pub fn get_local_path(&self) -> &str {
+        self.local_path.as_ref().unwrap_or(&self.path)
+    }
+
+    pub fn get_remote_path(&self) -> &str {
+        self.remote_path.as_ref().unwrap_or(&self.path)
+    }
+
+    /// Add a file, directory or wildcard below `path` to synchronize.
+    pub fn sub_path(mut self, path: String) -> Self {
+        self.paths.push(path);
+        self
+    }
+
+    pub fn tag(mut self, tag: String) -> Self {
+        self.tags.push(tag);
+        self
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    pub fn node_path(mut self, node_name: String, path: String) -> Self {
+        self.node_paths.insert(node_name, path);
+        self
+    }
+
+    /// Path of the target on the given node, before the node's own rewrites.
+    pub fn get_remote_path_for(&self, node_name: &str) -> &str {
+        self.node_paths.get(node_name).map_or(
+            self.get_remote_path(),
+            |p| p.as_str(),
+        )
+    }
+
+    /// Copy of the target with the variables of its paths expanded for the given node.
+    pub fn expanded(&self, node_name: &str) -> Result<Target, String> {
+        let expander = PathExpander::new(
+            format!("Target {}", self.name),
+            node_name,
+            Some(&self.name),
+        );
+
+        let mut target = self.clone();
+        target.path = expander.expand(&self.path)?;
+        target.local_path = expander.expand_option(&self.local_path)?;
+        target.remote_path = expander.expand_option(&self.remote_path)?;
+        target.paths = self.paths
+            .iter()
+            .map(|p| expander.expand(p))
+            .collect::<Result<_, _>>()?;
+        for path in target.node_paths.values_mut() {
+            *path = expander.expand(path)?;
+        }
+        Ok(target)
+    }
+
+    /// Name of the setting `get_remote_path_for` takes the path from.
+    pub fn remote_path_source(&self, node_name: &str) -> String {
+        if self.node_paths.contains_key(node_name) {
+            format!("node_paths[{}]", node_name)
+        } else if self.remote_path.is_some() {
+            String::from("remote_path")
+        } else {
+            String::from("path")
         }
     }
+
+    pub fn mode(mut self, mode: SyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn push_mode(mut self, mode: SyncMode) -> Self {
+        self.push_mode = Some(mode);
+        self
+    }
+
+    pub fn pull_mode(mut self, mode: SyncMode) -> Self {
+        self.pull_mode = Some(mode);
+        self
+    }
+
+    /// Mode used when synchronizing the target in the given direction.
+    pub fn mode_for(&self, direction: Direction) -> SyncMode {
+        match direction {
+            Direction::Push => self.push_mode,
+            Direction::Pull => self.pull_mode,
+        }.unwrap_or(self.mode)
+    }
+
+    pub fn allowed_direction(mut self, direction: Direction) -> Self {
+        self.allowed_directions.push(direction);
+        self
+    }
+
+    pub fn allows_direction(&self, direction: Direction) -> bool {
+        self.allowed_directions.is_empty() || self.allowed_directions.contains(&direction)
+    }
+
+    pub fn privileged(mut self, privileged: bool) -> Self {
+        self.privileged = privileged;
+        self
+    }
+
+    pub fn local_sudo(mut self, local_sudo: bool) -> Self {
+        self.local_sudo = local_sudo;
+        self
+    }
 }
 
 impl PartialOrd for Target {
@@ -40,3 +343,49 @@ impl PartialEq for Target {
         self.name == other.name
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std;
+    use super::{Target, TargetKind};
+
+    #[test]
+    fn rsync_path_of_directories_ends_with_slash() {
+        let dir = std::env::temp_dir().join(format!("eriksync-target-dir-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        for path in &[dir_str.to_string(), format!("{}/", dir_str)] {
+            let target = Target::new(String::from("dir"), path.clone());
+            assert_eq!(target.get_kind(), TargetKind::Dir);
+            assert_eq!(target.rsync_path(path), format!("{}/", dir_str));
+            assert_eq!(target.rsync_path("nas:/srv/dir"), "nas:/srv/dir/");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rsync_path_of_files_has_no_trailing_slash() {
+        let file = std::env::temp_dir().join(format!("eriksync-target-file-{}", std::process::id()));
+        std::fs::write(&file, "notes").unwrap();
+        let file_str = file.to_str().unwrap();
+
+        for path in &[file_str.to_string(), format!("{}/", file_str)] {
+            let target = Target::new(String::from("file"), path.clone());
+            assert_eq!(target.get_kind(), TargetKind::File);
+            assert_eq!(target.rsync_path(path), file_str);
+            assert_eq!(target.rsync_path("nas:/srv/notes.txt/"), "nas:/srv/notes.txt");
+        }
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn rsync_path_follows_explicit_kind() {
+        let mut target = Target::new(String::from("missing"), String::from("/does/not/exist/"));
+        assert_eq!(target.rsync_path("/does/not/exist"), "/does/not/exist/");
+        target.kind = Some(TargetKind::File);
+        assert_eq!(target.rsync_path("/does/not/exist/"), "/does/not/exist");
+    }
+}
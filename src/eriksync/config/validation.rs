@@ -0,0 +1,192 @@
+use std;
+use utils;
+use super::{Config, NodeKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The configuration cannot be used as it is.
+    Error,
+    /// Likely a mistake, but the configuration still works.
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem with a node, target or group of a configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    /// The offending entry, such as `node laptop` or `target docs`.
+    pub entry: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.entry, self.message)
+    }
+}
+
+struct Issues(Vec<Issue>);
+
+impl Issues {
+    fn error(&mut self, entry: String, message: String) {
+        self.0.push(Issue {
+            severity: Severity::Error,
+            entry,
+            message,
+        });
+    }
+
+    fn warning(&mut self, entry: String, message: String) {
+        self.0.push(Issue {
+            severity: Severity::Warning,
+            entry,
+            message,
+        });
+    }
+}
+
+/// Check the configuration for entries that cannot work or are likely mistakes.
+pub fn validate(config: &Config) -> Vec<Issue> {
+    let mut issues = Issues(Vec::new());
+    validate_nodes(config, &mut issues);
+    validate_targets(config, &mut issues);
+    validate_groups(config, &mut issues);
+    issues.0
+}
+
+fn validate_nodes(config: &Config, issues: &mut Issues) {
+    for node in config.nodes() {
+        let entry = format!("node {}", node.name);
+        if node.name.is_empty() {
+            issues.error(entry.clone(), String::from("name is empty"));
+        }
+        if node.name.to_lowercase() == "all" {
+            issues.error(entry.clone(), String::from("name collides with the `all` keyword"));
+        }
+        if node.name.contains(':') || node.name.contains(char::is_whitespace) {
+            issues.error(
+                entry.clone(),
+                String::from("name contains `:` or whitespace, which breaks `node:path` specs"),
+            );
+        }
+        if config.get_group(&node.name).is_some() {
+            issues.warning(entry.clone(), String::from("a group of the same name is shadowed"));
+        }
+
+        match node.kind {
+            NodeKind::Local if node.mount_point.as_ref().is_none_or(|m| m.is_empty()) => {
+                issues.error(entry.clone(), String::from("local node has no mount_point"));
+            }
+            NodeKind::Daemon if node.module.as_ref().is_none_or(|m| m.is_empty()) => {
+                issues.error(entry.clone(), String::from("daemon node has no module"));
+            }
+            _ => {}
+        }
+        if node.remote_root.as_ref().is_some_and(|r| r.is_empty()) {
+            issues.error(entry.clone(), String::from("remote_root is empty"));
+        }
+        for (from, to) in &node.path_rewrites {
+            if from.is_empty() || to.is_empty() {
+                issues.error(entry.clone(), format!("path rewrite `{}` -> `{}` is empty", from, to));
+            }
+        }
+
+        for pattern in &node.targets {
+            let name = pattern.trim_start_matches('!');
+            if name.starts_with('@') || utils::has_wildcards(name) {
+                continue;
+            }
+            if !config.contains_target(&name.to_string()) {
+                issues.warning(entry.clone(), format!("default target {} does not exist", name));
+            }
+        }
+    }
+}
+
+fn validate_targets(config: &Config, issues: &mut Issues) {
+    let targets = config.targets();
+    for target in &targets {
+        let entry = format!("target {}", target.name);
+        if target.name.to_lowercase() == "all" {
+            issues.error(entry.clone(), String::from("name collides with the `all` keyword"));
+        }
+        if target.name.starts_with('@') || target.name.starts_with('!') {
+            issues.error(
+                entry.clone(),
+                String::from("name starts with `@` or `!`, which select tags or remove targets"),
+            );
+        }
+
+        if target.path.is_empty() {
+            issues.error(entry.clone(), String::from("path is empty"));
+        }
+        if target.local_path.as_ref().is_some_and(|p| p.is_empty()) {
+            issues.error(entry.clone(), String::from("local_path is empty"));
+        }
+        if target.remote_path.as_ref().is_some_and(|p| p.is_empty()) {
+            issues.error(entry.clone(), String::from("remote_path is empty"));
+        }
+        if target.paths.iter().any(|p| p.is_empty()) {
+            issues.error(entry.clone(), String::from("paths contains an empty path"));
+        }
+        for (node, path) in &target.node_paths {
+            if path.is_empty() {
+                issues.error(entry.clone(), format!("path on node {} is empty", node));
+            }
+            if !config.contains_node(node) {
+                issues.warning(entry.clone(), format!("node_paths names unknown node {}", node));
+            }
+        }
+    }
+
+    // Targets inside other targets get their files synchronized twice.
+    let local_path = |target: &super::Target| {
+        let path = utils::expand_user(std::path::Path::new(target.get_local_path()));
+        String::from(path.to_str().unwrap_or_default().trim_end_matches('/'))
+    };
+    for outer in &targets {
+        let outer_path = local_path(outer);
+        if outer_path.is_empty() {
+            continue;
+        }
+        for inner in targets.iter().filter(|t| t.name != outer.name) {
+            let inner_path = local_path(inner);
+            if inner_path == outer_path {
+                if inner.name < outer.name {
+                    issues.warning(
+                        format!("target {}", inner.name),
+                        format!("has the same local path as target {}", outer.name),
+                    );
+                }
+            } else if inner_path.starts_with(&format!("{}/", outer_path)) {
+                issues.warning(
+                    format!("target {}", inner.name),
+                    format!("is nested inside target {}", outer.name),
+                );
+            }
+        }
+    }
+}
+
+fn validate_groups(config: &Config, issues: &mut Issues) {
+    for name in config.group_names() {
+        let entry = format!("group {}", name);
+        if name.to_lowercase() == "all" {
+            issues.error(entry.clone(), String::from("name collides with the `all` keyword"));
+        }
+        for member in config.get_group(&name).unwrap() {
+            if !config.contains_node(member) {
+                issues.error(entry.clone(), format!("member {} is not a node", member));
+            }
+        }
+    }
+}
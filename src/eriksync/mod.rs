@@ -1,3 +1,4 @@
 pub mod config;
 
-pub use self::config::{Config, Node, Target, ConfigFormat};
+pub use self::config::{Config, Direction, Hook, Node, NodeKind, Target, TargetKind, SyncMode,
+                       ConfigFormat};
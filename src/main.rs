@@ -8,17 +8,25 @@ extern crate errln;
 #[macro_use]
 extern crate serde_derive;
 
+#[macro_use]
+extern crate schemars;
+
 #[macro_use]
 extern crate clap;
 
 mod eriksync;
+mod node_check;
 mod rsync_command;
+mod ssh_config;
 mod utils;
 
 use clap::{Arg, App, SubCommand};
 
 use eriksync::config;
 
+/// Exit status for an unreadable, malformed or invalid configuration (`EX_CONFIG`).
+const EXIT_CONFIG_ERROR: i32 = 78;
+
 const APP_INFO: app_dirs::AppInfo = app_dirs::AppInfo {
     name: crate_name!(),
     author: "me",
@@ -48,14 +56,15 @@ fn create_config_file(
 }
 
 fn extract_options(cmd: &clap::ArgMatches) -> (String, Vec<String>) {
-    let values = cmd.values_of("").expect("No options.");
-    let mut iter = values.into_iter();
+    let mut iter = match cmd.values_of("") {
+        Some(values) => values.into_iter(),
+        None => {
+            println!("{}", cmd.usage());
+            return (String::new(), Vec::new());
+        }
+    };
     let node = iter.next().expect("No node name.");
     let targets: Vec<String> = iter.map(|t| t.to_string()).collect();
-    if targets.is_empty() {
-        println!("{}", cmd.usage());
-        return (String::new(), Vec::new());
-    }
 
     (node.to_string(), targets)
 }
@@ -74,11 +83,170 @@ pub fn build_cli() -> App<'static, 'static> {
         true,
     );
 
+    let arg_remote_path = Arg::with_name("remote-path")
+        .long("remote-path")
+        .takes_value(true)
+        .help("path on the nodes when it differs from --path");
+
     let arg_description = Arg::with_name("description")
         .long("description")
         .short("d")
         .takes_value(true);
 
+    let arg_host = Arg::with_name("host").long("host").takes_value(true).help(
+        "host name or address of the node, defaults to the node name",
+    );
+
+    let arg_user = Arg::with_name("user").long("user").short("u").takes_value(true);
+
+    let arg_port = Arg::with_name("port").long("port").takes_value(true);
+
+    let arg_identity_file = Arg::with_name("identity-file")
+        .long("identity-file")
+        .short("i")
+        .takes_value(true);
+
+    let arg_proxy_jump = Arg::with_name("proxy-jump")
+        .long("proxy-jump")
+        .short("J")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("jump host used to reach the node, may be repeated");
+
+    let arg_ssh_program = Arg::with_name("ssh-program").long("ssh-program").takes_value(
+        true,
+    );
+
+    let arg_ssh_option = Arg::with_name("ssh-option")
+        .long("ssh-option")
+        .short("o")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("ssh option in Key=Value form, may be repeated");
+
+    let arg_remote_root = Arg::with_name("remote-root")
+        .long("remote-root")
+        .takes_value(true)
+        .help("directory on the node that home-relative target paths are placed under");
+
+    let arg_kind = Arg::with_name("kind")
+        .long("kind")
+        .takes_value(true)
+        .possible_values(&["ssh", "local", "daemon"])
+        .help("how the node is reached");
+
+    let arg_mount_point = Arg::with_name("mount-point")
+        .long("mount-point")
+        .takes_value(true)
+        .help("mount point of a local node");
+
+    let arg_module = Arg::with_name("module")
+        .long("module")
+        .takes_value(true)
+        .help("rsync module of a daemon node");
+
+    let arg_password_file = Arg::with_name("password-file")
+        .long("password-file")
+        .takes_value(true)
+        .help("file holding the password of a daemon node");
+
+    let arg_target = Arg::with_name("target")
+        .long("target")
+        .short("t")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("target synchronized with the node by default, may be repeated");
+
+    let arg_rsync_path = Arg::with_name("rsync-path")
+        .long("rsync-path")
+        .takes_value(true)
+        .help("path of rsync on the node");
+
+    let arg_privileged = Arg::with_name("privileged").long("privileged").help(
+        "run rsync on the node through sudo and keep file ownership",
+    );
+
+    let arg_local_sudo = Arg::with_name("local-sudo").long("local-sudo").help(
+        "run the local rsync through sudo",
+    );
+
+    let arg_exclude = Arg::with_name("exclude")
+        .long("exclude")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("pattern excluded from the target, may be repeated");
+
+    let arg_include = Arg::with_name("include")
+        .long("include")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("pattern included even if it is excluded, may be repeated");
+
+    let modes = ["mirror", "update", "additive", "ignore-existing"];
+    let arg_mode = Arg::with_name("mode")
+        .long("mode")
+        .takes_value(true)
+        .possible_values(&modes)
+        .help("how the destination is brought up to date");
+
+    let arg_push_mode = Arg::with_name("push-mode")
+        .long("push-mode")
+        .takes_value(true)
+        .possible_values(&modes)
+        .help("mode used when pushing, overrides --mode");
+
+    let arg_pull_mode = Arg::with_name("pull-mode")
+        .long("pull-mode")
+        .takes_value(true)
+        .possible_values(&modes)
+        .help("mode used when pulling, overrides --mode");
+
+    let arg_force = Arg::with_name("force").long("force").short("f").help(
+        "sync even if the node or a target does not allow the direction",
+    );
+
+    let arg_read_only = Arg::with_name("read-only").long("read-only").help(
+        "refuse to push to the node",
+    );
+
+    let arg_allow = Arg::with_name("allow")
+        .long("allow")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .possible_values(&["push", "pull"])
+        .help("direction the target may be synchronized in, both by default");
+
+    let arg_file = Arg::with_name("file").long("file").help(
+        "the target is a single file rather than a directory",
+    );
+
+    let arg_sub_path = Arg::with_name("sub-path")
+        .long("sub-path")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("file, directory or wildcard below --path to sync instead of all of it, may be repeated");
+
+    let arg_node_path = Arg::with_name("node-path")
+        .long("node-path")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("path on a specific node as node=path, may be repeated");
+
+    let arg_tag = Arg::with_name("tag")
+        .long("tag")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+        .help("tag of the target, may be repeated");
+
     let arg_replace = Arg::with_name("replace").long("replace").short("r").help(
         "replace files/folders if they already exist",
     );
@@ -97,6 +265,9 @@ pub fn build_cli() -> App<'static, 'static> {
         .subcommand(SubCommand::with_name("version").about(
             "Show version of Eriksync",
         ))
+        .subcommand(SubCommand::with_name("schema").about(
+            "Print the JSON Schema of configuration files, for YAML, TOML and JSON alike",
+        ))
         .subcommand(
             SubCommand::with_name("completions")
                 .about("Generate shell completions")
@@ -123,7 +294,22 @@ pub fn build_cli() -> App<'static, 'static> {
             SubCommand::with_name("add-node")
                 .about("Add node")
                 .arg(arg_name.clone())
-                .arg(arg_description.clone()),
+                .arg(arg_description.clone())
+                .arg(arg_kind.clone())
+                .arg(arg_read_only.clone())
+                .arg(arg_host.clone())
+                .arg(arg_user.clone())
+                .arg(arg_port.clone())
+                .arg(arg_identity_file.clone())
+                .arg(arg_proxy_jump.clone())
+                .arg(arg_ssh_program.clone())
+                .arg(arg_ssh_option.clone())
+                .arg(arg_rsync_path.clone())
+                .arg(arg_target.clone())
+                .arg(arg_remote_root.clone())
+                .arg(arg_mount_point.clone())
+                .arg(arg_module.clone())
+                .arg(arg_password_file.clone()),
         )
         .subcommand(
             SubCommand::with_name("remove-node")
@@ -134,46 +320,139 @@ pub fn build_cli() -> App<'static, 'static> {
             SubCommand::with_name("add-target")
                 .about("Add target")
                 .arg(arg_name.clone())
-                .arg(arg_path.clone()),
+                .arg(arg_path.clone())
+                .arg(arg_remote_path.clone())
+                .arg(arg_node_path.clone())
+                .arg(arg_file.clone())
+                .arg(arg_sub_path.clone())
+                .arg(arg_tag.clone())
+                .arg(arg_mode.clone())
+                .arg(arg_push_mode.clone())
+                .arg(arg_pull_mode.clone())
+                .arg(arg_allow.clone())
+                .arg(arg_privileged.clone())
+                .arg(arg_local_sudo.clone())
+                .arg(arg_exclude.clone())
+                .arg(arg_include.clone()),
         )
         .subcommand(
             SubCommand::with_name("remove-target")
                 .about("Remove target")
                 .arg(arg_name.clone()),
         )
+        .subcommand(
+            SubCommand::with_name("add-group")
+                .about("Add group of nodes")
+                .arg(arg_name.clone())
+                .arg(
+                    Arg::with_name("nodes")
+                        .multiple(true)
+                        .takes_value(true)
+                        .help("nodes in the group"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("remove-group")
+                .about("Remove group of nodes")
+                .arg(arg_name.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("import-ssh-config")
+                .about("Add nodes from hosts of ssh configuration")
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .short("f")
+                        .takes_value(true)
+                        .help("ssh configuration file, defaults to ~/.ssh/config"),
+                )
+                .arg(Arg::with_name("all").long("all").short("a").help(
+                    "import every host without asking",
+                ))
+                .arg(Arg::with_name("dry-run").long("dry-run").help(
+                    "show the nodes that would be added without saving them",
+                ))
+                .arg(arg_replace.clone())
+                .arg(
+                    Arg::with_name("hosts")
+                        .multiple(true)
+                        .takes_value(true)
+                        .help("hosts to import"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check-node")
+                .alias("doctor")
+                .about("Check reachability of nodes and the state of targets on them")
+                .arg(
+                    Arg::with_name("node")
+                        .required(true)
+                        .takes_value(true)
+                        .help("node_name|group_name|all"),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .short("t")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("seconds to wait for a node to answer"),
+                ),
+        )
         .subcommand(SubCommand::with_name("list-nodes").about("Print nodes"))
-        .subcommand(SubCommand::with_name("list-targets").about("Print targets"))
+        .subcommand(SubCommand::with_name("list-groups").about("Print groups"))
+        .subcommand(
+            SubCommand::with_name("list-targets")
+                .about("Print targets")
+                .arg(arg_tag.clone().help("only print targets with the tag")),
+        )
         .subcommand(SubCommand::with_name("config-location").about(
             "Print location of configuration file",
         ))
         .subcommand(
             SubCommand::with_name("show-config")
                 .about("Print configuration")
-                .arg(arg_format.clone()),
+                .arg(arg_format.clone())
+                .arg(
+                    Arg::with_name("node")
+                        .long("node")
+                        .takes_value(true)
+                        .help("also print the path of every target on the node and why it was chosen"),
+                ),
         )
+        .subcommand(SubCommand::with_name("check-config").about(
+            "Check configuration for errors and likely mistakes",
+        ))
+        .subcommand(SubCommand::with_name("migrate-config").about(
+            "Upgrade configuration files to the current version, keeping backups",
+        ))
         .subcommand(
             SubCommand::with_name("push")
                 .about("Send data from local host to remote host")
-                .help("node_name [all|target1] [target2]......")
-                .arg(Arg::with_name("").multiple(true).takes_value(true)),
+                .help("node_name|group_name|all [all|target|@tag|!target|!@tag]...... (default: the node's targets)")
+                .arg(Arg::with_name("").multiple(true).takes_value(true))
+                .arg(arg_force.clone()),
         )
         .subcommand(
             SubCommand::with_name("pull")
                 .about("Send data from remote host to local host")
-                .help("node_name [all|target1] [target2]......")
-                .arg(Arg::with_name("").multiple(true).takes_value(true)),
+                .help("node_name|group_name|all [all|target|@tag|!target|!@tag]...... (default: the node's targets)")
+                .arg(Arg::with_name("").multiple(true).takes_value(true))
+                .arg(arg_force.clone()),
         )
         .subcommand(
             SubCommand::with_name("dry-push")
                 .about("Show commands of push without transfering data")
-                .help("node_name [all|target1] [target2]......")
-                .arg(Arg::with_name("").multiple(true).takes_value(true)),
+                .help("node_name|group_name|all [all|target|@tag|!target|!@tag]...... (default: the node's targets)")
+                .arg(Arg::with_name("").multiple(true).takes_value(true))
+                .arg(arg_force.clone()),
         )
         .subcommand(
             SubCommand::with_name("dry-pull")
                 .about("Show commands of pull without transfering data")
-                .help("node_name [all|target1] [target2]......")
-                .arg(Arg::with_name("").multiple(true).takes_value(true)),
+                .help("node_name|group_name|all [all|target|@tag|!target|!@tag]...... (default: the node's targets)")
+                .arg(Arg::with_name("").multiple(true).takes_value(true))
+                .arg(arg_force.clone()),
         )
 }
 
@@ -187,14 +466,277 @@ pub fn extract_format(cmd: &clap::ArgMatches) -> config::ConfigFormat {
 }
 
 pub fn save_config(config: &config::Config, path: &std::path::Path) {
-    if let Some(format) = config::ConfigFormat::from_path(path) {
-        match config.save_file(path, format) {
-            Ok(_) => {}
-            Err(e) => panic!(e), 
+    let result = match config::ConfigFormat::from_path(path) {
+        Some(format) => config.save_file(path, format),
+        None => Err(config::ConfigError::UnknownFormat(path.to_path_buf())),
+    };
+    match result {
+        Ok(Some(backup)) => {
+            println!(
+                "Upgraded {:?} to version {}, original saved as {:?}",
+                path,
+                config::CONFIG_VERSION,
+                backup
+            )
+        }
+        Ok(None) => {}
+        Err(e) => {
+            errln!("Failed to save configuration: {}", e);
+            std::process::exit(EXIT_CONFIG_ERROR);
+        }
+    }
+}
+
+/// Report an entry defined in an included file, which saving the main file cannot change.
+fn is_included(config: &config::Config, kind: &str, name: &str) -> bool {
+    match config.included_from(kind, name) {
+        Some(source) => {
+            errln!("The {} {} is defined in {:?}, edit that file instead", kind, name, source);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Print problems found in the configuration, returning whether any is an error.
+fn report_issues(issues: &[config::Issue]) -> bool {
+    for issue in issues {
+        errln!("{}", issue);
+    }
+    issues.iter().any(|i| i.severity == config::Severity::Error)
+}
+
+/// Apply a change and report the problems it introduces. Returns false when the
+/// change introduced errors and must not be saved.
+fn check_change<F>(config: &mut config::Config, change: F) -> bool
+where
+    F: FnOnce(&mut config::Config),
+{
+    let before = config.validate();
+    change(config);
+    let issues: Vec<config::Issue> = config
+        .validate()
+        .into_iter()
+        .filter(|issue| !before.contains(issue))
+        .collect();
+    !report_issues(&issues)
+}
+
+fn check_config(config: &config::Config) -> bool {
+    for (path, version) in config.outdated_files() {
+        println!(
+            "note: {:?} is written in configuration version {}, run migrate-config to upgrade it",
+            path,
+            version
+        );
+    }
+
+    let issues = config.validate();
+    if issues.is_empty() {
+        println!("No problems found");
+        return true;
+    }
+    let has_errors = report_issues(&issues);
+    let errors = issues
+        .iter()
+        .filter(|i| i.severity == config::Severity::Error)
+        .count();
+    println!("{} error(s), {} warning(s)", errors, issues.len() - errors);
+    !has_errors
+}
+
+fn migrate_config(config: &config::Config) -> bool {
+    let outdated = config.outdated_files();
+    if outdated.is_empty() {
+        println!("Configuration is already at version {}", config::CONFIG_VERSION);
+        return true;
+    }
+    for (path, version) in outdated {
+        match config::Config::migrate_file(path) {
+            Ok(Some(backup)) => {
+                println!(
+                    "Migrated {:?} from version {} to {}, original saved as {:?}",
+                    path,
+                    version,
+                    config::CONFIG_VERSION,
+                    backup
+                )
+            }
+            Ok(None) => {}
+            Err(e) => {
+                errln!("Failed to migrate configuration: {}", e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn confirm(question: &str) -> bool {
+    use std::io::Write;
+
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush().unwrap_or_default();
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    let answer = answer.trim().to_lowercase();
+    answer == "y" || answer == "yes"
+}
+
+fn import_ssh_config(config: &mut config::Config, cmd: &clap::ArgMatches) -> bool {
+    let path = cmd.value_of("file").map_or_else(
+        ssh_config::default_path,
+        |file| utils::expand_user(std::path::Path::new(file)),
+    );
+    let ssh_config = match ssh_config::SshConfig::load_file(&path) {
+        Ok(ssh_config) => ssh_config,
+        Err(e) => {
+            errln!("{}", e);
+            return false;
         }
-    } else {
-        println!("{:?} Unknown format", path);
+    };
+
+    let hosts = match cmd.values_of("hosts") {
+        Some(hosts) => {
+            let known = ssh_config.hosts();
+            let hosts: Vec<String> = hosts.map(|h| h.to_string()).collect();
+            if let Some(unknown) = hosts.iter().find(|h| !known.contains(h)) {
+                errln!("No such host in {:?}: {}", path, unknown);
+                return false;
+            }
+            hosts
+        }
+        None => ssh_config.hosts(),
+    };
+
+    let interactive = !cmd.is_present("all") && !cmd.is_present("hosts") &&
+        !cmd.is_present("dry-run");
+    let mut imported = config::Config::new();
+    for host in hosts {
+        if config.contains_node(&host) && !cmd.is_present("replace") {
+            println!("Skipping {}: node already exists", host);
+            continue;
+        }
+        if let Some(source) = config.included_from("node", &host) {
+            println!("Skipping {}: node is defined in {:?}", host, source);
+            continue;
+        }
+        let node = ssh_config.node(&host);
+        if !interactive || confirm(&format!("Import {} ({})?", host, node.destination())) {
+            imported.add_node(node);
+        }
+    }
+
+    if imported.nodes().is_empty() {
+        println!("No nodes to import");
+        return false;
+    }
+    if cmd.is_present("dry-run") {
+        println!("{}", imported.to_yaml_string());
+        return false;
+    }
+    if interactive {
+        println!("{}", imported.to_yaml_string());
+        if !confirm("Save these nodes?") {
+            return false;
+        }
+    }
+
+    for node in imported.nodes() {
+        println!("Added node {}", node.name);
+        config.add_node(node);
+    }
+    !imported.nodes().is_empty()
+}
+
+fn check_nodes(config: &config::Config, cmd: &clap::ArgMatches) -> bool {
+    let name = cmd.value_of("node").unwrap_or_default().to_string();
+    let timeout = match value_t!(cmd, "timeout", u64) {
+        Ok(timeout) => std::time::Duration::from_secs(timeout),
+        Err(e) => {
+            errln!("{}", e);
+            return false;
+        }
+    };
+    let nodes = match config.resolve_nodes(&name) {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            errln!("{}", e);
+            return false;
+        }
+    };
+
+    let checks: Vec<node_check::Check> = nodes
+        .iter()
+        .flat_map(|node| node_check::probe_node(config, node, timeout))
+        .collect();
+    node_check::print_checks(&checks);
+
+    checks.iter().all(
+        |check| check.status != node_check::Status::Fail,
+    )
+}
+
+fn sync(
+    config: &config::Config,
+    cmd: &clap::ArgMatches,
+    direction: rsync_command::Direction,
+    dry_run: bool,
+) -> bool {
+    let (name, targets) = extract_options(cmd);
+    if name.is_empty() {
+        return false;
+    }
+    let force = cmd.is_present("force");
+
+    let nodes = match config.resolve_nodes(&name) {
+        Ok(nodes) => nodes,
+        Err(e) => {
+            errln!("{}", e);
+            return false;
+        }
+    };
+
+    let fan_out = nodes.len() > 1;
+    let mut results = Vec::new();
+    for node in nodes {
+        if fan_out {
+            println!("==> {}", node);
+        }
+        let result = rsync_command::generate_commands(config, &node, &targets, direction, force)
+            .and_then(|commands| if dry_run {
+                let names: Vec<&str> = commands.iter().map(|c| c.target()).collect();
+                if names.is_empty() {
+                    println!("Selected targets: (none)");
+                } else {
+                    println!("Selected targets: {}", names.join(", "));
+                }
+                rsync_command::show_paths(&commands);
+                rsync_command::show_commands(&commands);
+                Ok(())
+            } else {
+                rsync_command::run_commands(&commands)
+            });
+        if let Err(ref e) = result {
+            errln!("{}", e);
+        }
+        results.push((node, result));
     }
+
+    if fan_out {
+        println!("Summary:");
+        for (node, result) in &results {
+            match result {
+                Ok(_) => println!("  {}: ok", node),
+                Err(e) => println!("  {}: failed ({})", node, e),
+            }
+        }
+    }
+
+    results.iter().all(|(_, result)| result.is_ok())
 }
 
 fn default_config_file_path(format: config::ConfigFormat) -> std::path::PathBuf {
@@ -231,6 +773,9 @@ fn main() {
             };
             cli.gen_completions_to(crate_name!(), shell, &mut std::io::stdout());
         }
+        ("schema", Some(_)) => {
+            println!("{}", config::Config::json_schema());
+        }
         ("init", Some(cmd)) => {
             let format = extract_format(cmd);
             let config_file = default_config_file_path(format.clone());
@@ -255,16 +800,22 @@ fn main() {
             let mut config = match eriksync::Config::load_file(config_file.as_path()) {
                 Ok(config) => config,
                 Err(e) => {
-                    errln!(
-                        "Failed to load configuration file: {:?}, error: {:?}",
-                        config_file.as_path(),
-                        e
-                    );
-                    return;
-                } 
+                    errln!("Failed to load configuration: {}", e);
+                    std::process::exit(EXIT_CONFIG_ERROR);
+                }
             };
 
             match matches.subcommand() {
+                ("check-config", Some(_)) => {
+                    if !check_config(&config) {
+                        std::process::exit(EXIT_CONFIG_ERROR);
+                    }
+                }
+                ("migrate-config", Some(_)) => {
+                    if !migrate_config(&config) {
+                        std::process::exit(EXIT_CONFIG_ERROR);
+                    }
+                }
                 ("config-location", Some(_)) => {
                     println!("{:?}", config_file);
                 }
@@ -278,15 +829,59 @@ fn main() {
                             config::ConfigFormat::toml => config.to_toml_string(),
                         }
                     );
+                    if let Some(node_name) = cmd.value_of("node") {
+                        let node = match config.get_node(&node_name.to_string()).map(
+                            |node| node.expanded(),
+                        ) {
+                            Some(Ok(node)) => node,
+                            Some(Err(e)) => {
+                                errln!("{}", e);
+                                std::process::exit(1);
+                            }
+                            None => {
+                                errln!("No such node: {}", node_name);
+                                std::process::exit(1);
+                            }
+                        };
+                        for target in config.targets() {
+                            match target.expanded(&node.name) {
+                                Ok(target) => {
+                                    println!(
+                                        "# {}",
+                                        rsync_command::explain_remote_path(&node, &target)
+                                    )
+                                }
+                                Err(e) => errln!("{}", e),
+                            }
+                        }
+                    }
+                }
+                ("check-node", Some(cmd)) => {
+                    if !check_nodes(&config, cmd) {
+                        std::process::exit(1);
+                    }
                 }
                 ("list-nodes", Some(_)) => {
                     for node in config.nodes() {
                         println!("{}: {}", node.name, node.description);
                     }
                 }
-                ("list-targets", Some(_)) => {
+                ("list-targets", Some(cmd)) => {
+                    let tags: Vec<&str> = cmd.values_of("tag").map_or(Vec::new(), |tags| {
+                        tags.map(|t| t.trim_start_matches('@')).collect()
+                    });
                     for target in config.targets() {
-                        println!("{}: {}", target.name, target.path);
+                        if !tags.iter().all(|tag| target.has_tag(tag)) {
+                            continue;
+                        }
+                        let mut line = format!("{}: {}", target.name, target.get_local_path());
+                        if target.get_local_path() != target.get_remote_path() {
+                            line.push_str(&format!(" (remote: {})", target.get_remote_path()));
+                        }
+                        if !target.tags.is_empty() {
+                            line.push_str(&format!(" [@{}]", target.tags.join(", @")));
+                        }
+                        println!("{}", line);
                     }
                 }
                 ("add-node", Some(cmd)) => {
@@ -294,60 +889,220 @@ fn main() {
                     let desc = cmd.value_of("description")
                         .expect("Node desctiption")
                         .to_string();
-                    config.add_node(eriksync::Node::new(name).description(desc));
+                    let mut node = eriksync::Node::new(name).description(desc).read_only(
+                        cmd.is_present("read-only"),
+                    );
+                    if let Some(kind) = cmd.value_of("kind").and_then(eriksync::NodeKind::from_str) {
+                        node = node.kind(kind);
+                    }
+                    if let Some(host) = cmd.value_of("host") {
+                        node = node.host(host.to_string());
+                    }
+                    if let Some(user) = cmd.value_of("user") {
+                        node = node.user(user.to_string());
+                    }
+                    if let Some(port) = cmd.value_of("port") {
+                        match port.parse::<u16>() {
+                            Ok(port) => node = node.port(port),
+                            Err(_) => {
+                                errln!("Invalid port: {}", port);
+                                return;
+                            }
+                        }
+                    }
+                    if let Some(identity_file) = cmd.value_of("identity-file") {
+                        node = node.identity_file(identity_file.to_string());
+                    }
+                    if let Some(jump_hosts) = cmd.values_of("proxy-jump") {
+                        for jump_host in jump_hosts {
+                            node = node.proxy_jump(jump_host.to_string());
+                        }
+                    }
+                    if let Some(ssh_program) = cmd.value_of("ssh-program") {
+                        node = node.ssh_program(ssh_program.to_string());
+                    }
+                    if let Some(options) = cmd.values_of("ssh-option") {
+                        for option in options {
+                            node = node.ssh_option(option.to_string());
+                        }
+                    }
+                    if let Some(rsync_path) = cmd.value_of("rsync-path") {
+                        node = node.rsync_path(rsync_path.to_string());
+                    }
+                    if let Some(targets) = cmd.values_of("target") {
+                        for target in targets {
+                            node = node.target(target.to_string());
+                        }
+                    }
+                    if let Some(remote_root) = cmd.value_of("remote-root") {
+                        node = node.remote_root(remote_root.to_string());
+                    }
+                    if let Some(mount_point) = cmd.value_of("mount-point") {
+                        node = node.mount_point(mount_point.to_string());
+                    }
+                    if let Some(module) = cmd.value_of("module") {
+                        node = node.module(module.to_string());
+                    }
+                    if let Some(password_file) = cmd.value_of("password-file") {
+                        node = node.password_file(password_file.to_string());
+                    }
+                    if is_included(&config, "node", &node.name) {
+                        std::process::exit(1);
+                    }
+                    if !check_change(&mut config, |config| config.add_node(node)) {
+                        std::process::exit(EXIT_CONFIG_ERROR);
+                    }
                     save_config(&config, config_file.as_path());
                 }
                 ("remove-node", Some(cmd)) => {
                     let name = cmd.value_of("name").expect("Node name").to_string();
+                    if is_included(&config, "node", &name) {
+                        std::process::exit(1);
+                    }
                     config.remove_node(name);
                     save_config(&config, config_file.as_path());
                 }
+                ("add-group", Some(cmd)) => {
+                    let name = cmd.value_of("name").expect("Group name").to_string();
+                    let nodes: Vec<String> = cmd.values_of("nodes")
+                        .map_or(Vec::new(), |nodes| nodes.map(|n| n.to_string()).collect());
+                    if is_included(&config, "group", &name) {
+                        std::process::exit(1);
+                    }
+                    if !check_change(&mut config, |config| config.add_group(name, nodes)) {
+                        std::process::exit(EXIT_CONFIG_ERROR);
+                    }
+                    save_config(&config, config_file.as_path());
+                }
+                ("remove-group", Some(cmd)) => {
+                    let name = cmd.value_of("name").expect("Group name").to_string();
+                    if is_included(&config, "group", &name) {
+                        std::process::exit(1);
+                    }
+                    config.remove_group(name);
+                    save_config(&config, config_file.as_path());
+                }
+                ("list-groups", Some(_)) => {
+                    for name in config.group_names() {
+                        println!("{}: {}", name, config.get_group(&name).unwrap().join(", "));
+                    }
+                }
+                ("import-ssh-config", Some(cmd)) => {
+                    if import_ssh_config(&mut config, cmd) {
+                        save_config(&config, config_file.as_path());
+                    }
+                }
                 ("add-target", Some(cmd)) => {
                     let name = cmd.value_of("name").expect("Target name").to_string();
-                    let path = cmd.value_of("path").expect("Target path").to_string();
-                    config.add_target(eriksync::Target::new(name, path));
+                    let path = cmd.value_of("path").expect("Target path");
+                    let path = if path.len() > 1 {
+                        path.trim_end_matches('/')
+                    } else {
+                        path
+                    };
+                    let mut target = eriksync::Target::new(name, path.to_string())
+                        .privileged(cmd.is_present("privileged"))
+                        .local_sudo(cmd.is_present("local-sudo"));
+                    if let Some(node_paths) = cmd.values_of("node-path") {
+                        for node_path in node_paths {
+                            let mut parts = node_path.splitn(2, '=');
+                            match (parts.next(), parts.next()) {
+                                (Some(node), Some(path)) if !node.is_empty() => {
+                                    target = target.node_path(node.to_string(), path.to_string());
+                                }
+                                _ => {
+                                    errln!("Invalid node path, expected node=path: {}", node_path);
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    let is_file = cmd.is_present("file") ||
+                        utils::expand_user(std::path::Path::new(path)).is_file();
+                    if is_file {
+                        target = target.kind(eriksync::TargetKind::File);
+                    }
+                    if let Some(paths) = cmd.values_of("sub-path") {
+                        for path in paths {
+                            target = target.sub_path(path.to_string());
+                        }
+                    }
+                    if let Some(tags) = cmd.values_of("tag") {
+                        for tag in tags {
+                            target = target.tag(tag.trim_start_matches('@').to_string());
+                        }
+                    }
+                    if let Some(directions) = cmd.values_of("allow") {
+                        for direction in directions {
+                            target = target.allowed_direction(match direction {
+                                "push" => eriksync::Direction::Push,
+                                _ => eriksync::Direction::Pull,
+                            });
+                        }
+                    }
+                    if let Some(remote_path) = cmd.value_of("remote-path") {
+                        target = target.remote_path(remote_path.to_string());
+                    }
+                    if let Some(mode) = cmd.value_of("mode").and_then(eriksync::SyncMode::from_str) {
+                        target = target.mode(mode);
+                    }
+                    if let Some(mode) = cmd.value_of("push-mode").and_then(
+                        eriksync::SyncMode::from_str,
+                    )
+                    {
+                        target = target.push_mode(mode);
+                    }
+                    if let Some(mode) = cmd.value_of("pull-mode").and_then(
+                        eriksync::SyncMode::from_str,
+                    )
+                    {
+                        target = target.pull_mode(mode);
+                    }
+                    if let Some(patterns) = cmd.values_of("exclude") {
+                        for pattern in patterns {
+                            target = target.exclude(pattern.to_string());
+                        }
+                    }
+                    if let Some(patterns) = cmd.values_of("include") {
+                        for pattern in patterns {
+                            target = target.include(pattern.to_string());
+                        }
+                    }
+                    if is_included(&config, "target", &target.name) {
+                        std::process::exit(1);
+                    }
+                    if !check_change(&mut config, |config| config.add_target(target)) {
+                        std::process::exit(EXIT_CONFIG_ERROR);
+                    }
                     save_config(&config, config_file.as_path());
                 }
                 ("remove-target", Some(cmd)) => {
                     let name = cmd.value_of("name").expect("Target name").to_string();
+                    if is_included(&config, "target", &name) {
+                        std::process::exit(1);
+                    }
                     config.remove_target(name);
                     save_config(&config, config_file.as_path());
                 }
                 ("push", Some(cmd)) => {
-                    let (node, targets) = extract_options(cmd);
-                    rsync_command::run_commands(&rsync_command::generate_commands(
-                        &config,
-                        &node,
-                        &targets,
-                        rsync_command::Direction::Push,
-                    ));
+                    if !sync(&config, cmd, rsync_command::Direction::Push, false) {
+                        std::process::exit(1);
+                    }
                 }
                 ("pull", Some(cmd)) => {
-                    let (node, targets) = extract_options(cmd);
-                    rsync_command::run_commands(&rsync_command::generate_commands(
-                        &config,
-                        &node,
-                        &targets,
-                        rsync_command::Direction::Pull,
-                    ));
+                    if !sync(&config, cmd, rsync_command::Direction::Pull, false) {
+                        std::process::exit(1);
+                    }
                 }
                 ("dry-push", Some(cmd)) => {
-                    let (node, targets) = extract_options(cmd);
-                    rsync_command::show_commands(&rsync_command::generate_commands(
-                        &config,
-                        &node,
-                        &targets,
-                        rsync_command::Direction::Push,
-                    ));
+                    if !sync(&config, cmd, rsync_command::Direction::Push, true) {
+                        std::process::exit(1);
+                    }
                 }
                 ("dry-pull", Some(cmd)) => {
-                    let (node, targets) = extract_options(cmd);
-                    rsync_command::show_commands(&rsync_command::generate_commands(
-                        &config,
-                        &node,
-                        &targets,
-                        rsync_command::Direction::Pull,
-                    ));
+                    if !sync(&config, cmd, rsync_command::Direction::Pull, true) {
+                        std::process::exit(1);
+                    }
                 }
                 _ => {
                     cli.print_help().unwrap();
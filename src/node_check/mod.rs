@@ -0,0 +1,365 @@
+use std;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use eriksync;
+use rsync_command;
+use utils;
+
+/// Clock difference above which mtime comparisons between the hosts become unreliable.
+const MAX_CLOCK_SKEW_SECS: i64 = 2;
+
+/// Free space below which a target directory is reported as a warning.
+const LOW_SPACE_KB: u64 = 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Status::Pass => write!(f, "pass"),
+            Status::Warn => write!(f, "warn"),
+            Status::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+pub struct Check {
+    pub node: String,
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+struct Report {
+    node: String,
+    checks: Vec<Check>,
+}
+
+impl Report {
+    fn add(&mut self, name: &str, status: Status, detail: String) {
+        self.checks.push(Check {
+            node: self.node.clone(),
+            name: name.to_string(),
+            status,
+            detail,
+        });
+    }
+}
+
+/// Probe a node for reachability, its rsync and the state of the target directories on it.
+pub fn probe_node(config: &eriksync::Config, node_name: &String, timeout: Duration) -> Vec<Check> {
+    let mut report = Report {
+        node: node_name.clone(),
+        checks: Vec::new(),
+    };
+
+    let node = match config.get_node(node_name).map(|node| node.expanded()) {
+        Some(Ok(node)) => node,
+        Some(Err(e)) => {
+            report.add("config", Status::Fail, e);
+            return report.checks;
+        }
+        None => {
+            report.add("config", Status::Fail, String::from("no such node"));
+            return report.checks;
+        }
+    };
+    let targets: Result<Vec<eriksync::Target>, String> = config
+        .targets()
+        .into_iter()
+        .filter(|t| node.allows_target(t))
+        .map(|t| t.expanded(&node.name))
+        .collect();
+    let targets = match targets {
+        Ok(targets) => targets,
+        Err(e) => {
+            report.add("config", Status::Fail, e);
+            return report.checks;
+        }
+    };
+    let node = &node;
+
+    match node.kind {
+        eriksync::NodeKind::Ssh => probe_shell(node, &targets, timeout, &mut report),
+        eriksync::NodeKind::Local => {
+            match rsync_command::check_node(node) {
+                Ok(_) => {
+                    let mount_point = node.mount_point.clone().unwrap_or_default();
+                    report.add("mount", Status::Pass, format!("{} is mounted", mount_point));
+                    probe_shell(node, &targets, timeout, &mut report);
+                }
+                Err(e) => report.add("mount", Status::Fail, e),
+            }
+        }
+        eriksync::NodeKind::Daemon => probe_daemon(node, timeout, &mut report),
+    }
+    report.checks
+}
+
+pub fn print_checks(checks: &[Check]) {
+    let node_width = checks.iter().map(|c| c.node.len()).max().unwrap_or(0).max(4);
+    let name_width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0).max(5);
+
+    println!(
+        "{:node_width$}  {:name_width$}  {:6}  DETAIL",
+        "NODE",
+        "CHECK",
+        "STATUS",
+        node_width = node_width,
+        name_width = name_width
+    );
+    for check in checks {
+        println!(
+            "{:node_width$}  {:name_width$}  {:6}  {}",
+            check.node,
+            check.name,
+            check.status.to_string(),
+            check.detail,
+            node_width = node_width,
+            name_width = name_width
+        );
+    }
+}
+
+/// Run the probe script on the node over SSH, or locally for local nodes.
+fn probe_shell(
+    node: &eriksync::Node,
+    targets: &[eriksync::Target],
+    timeout: Duration,
+    report: &mut Report,
+) {
+    let script = probe_script(node, targets);
+    let remote = node.kind == eriksync::NodeKind::Ssh;
+    let mut command = if remote {
+        let args = rsync_command::ssh_args(node);
+        let mut command = std::process::Command::new(&args[0]);
+        command
+            .args(&args[1..])
+            .arg("-o")
+            .arg("BatchMode=yes")
+            .arg("-o")
+            .arg(format!("ConnectTimeout={}", timeout.as_secs()))
+            .arg(node.destination())
+            .arg(format!("sh -c {}", utils::shell_quote(&script)));
+        command
+    } else {
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg(&script);
+        command
+    };
+
+    let started = unix_time();
+    let output = match utils::output_with_timeout(&mut command, timeout * 2) {
+        Ok(output) => output,
+        Err(e) => {
+            report.add("connect", Status::Fail, e);
+            return;
+        }
+    };
+    let finished = unix_time();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    if !lines.iter().any(|l| l.starts_with("time ")) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("no response").trim().to_string();
+        report.add("connect", Status::Fail, reason);
+        return;
+    }
+    if remote {
+        report.add(
+            "connect",
+            Status::Pass,
+            format!("connected to {}", node.destination()),
+        );
+    }
+
+    for line in lines {
+        let mut fields = line.splitn(2, ' ');
+        let kind = fields.next().unwrap_or_default();
+        let rest = fields.next().unwrap_or_default().trim();
+        match kind {
+            "rsync" => {
+                match rsync_version(rest) {
+                    Some(version) => report.add("rsync", Status::Pass, version),
+                    None => report.add("rsync", Status::Fail, String::from("rsync not found")),
+                }
+            }
+            "time" if remote => {
+                if let Ok(remote_time) = rest.parse::<i64>() {
+                    let skew = remote_time - (started + finished) / 2;
+                    let tolerance = MAX_CLOCK_SKEW_SECS + (finished - started) / 2;
+                    if skew.abs() > tolerance {
+                        report.add(
+                            "clock",
+                            Status::Warn,
+                            format!("clock differs from local clock by {}s", skew),
+                        );
+                    } else {
+                        report.add("clock", Status::Pass, String::from("in sync"));
+                    }
+                }
+            }
+            "target" => {
+                let fields: Vec<&str> = rest.split_whitespace().collect();
+                let target = match fields.first().and_then(|i| i.parse::<usize>().ok()) {
+                    Some(index) if index < targets.len() => &targets[index],
+                    _ => continue,
+                };
+                let path = rsync_command::remote_path(node, target);
+                let name = format!("target {}", target.name);
+                let free = fields.get(2).and_then(|f| f.parse::<u64>().ok());
+                match fields.get(1).cloned() {
+                    Some("writable") => {
+                        let status = match free {
+                            Some(free) if free < LOW_SPACE_KB => Status::Warn,
+                            _ => Status::Pass,
+                        };
+                        let free = free.map_or(String::from("unknown"), format_kb);
+                        report.add(&name, status, format!("{} is writable, {} free", path, free));
+                    }
+                    Some("readonly") => {
+                        report.add(&name, Status::Fail, format!("{} is not writable", path))
+                    }
+                    _ => {
+                        report.add(
+                            &name,
+                            Status::Fail,
+                            format!("parent directory of {} does not exist", path),
+                        )
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Shell script printing the rsync version, the clock and the state of every target directory.
+fn probe_script(node: &eriksync::Node, targets: &[eriksync::Target]) -> String {
+    let mut script = vec![
+        String::from("printf 'time %s\\n' \"$(date +%s)\""),
+        format!(
+            "printf 'rsync %s\\n' \"$({} --version 2>/dev/null | head -n 1)\"",
+            utils::shell_quote(node.rsync_path.as_ref().map_or("rsync", |p| p.as_str()))
+        ),
+    ];
+    for (index, target) in targets.iter().enumerate() {
+        let path = rsync_command::remote_path(node, target);
+        script.push(format!(
+            "d={}; [ -d \"$d\" ] || d={}; \
+             if [ ! -d \"$d\" ]; then s=missing; elif [ -w \"$d\" ]; then s=writable; \
+             else s=readonly; fi; \
+             printf 'target {} %s %s\\n' \"$s\" \"$(df -Pk \"$d\" 2>/dev/null | awk 'NR==2 {{print $4}}')\"",
+            shell_path(&path),
+            shell_path(&parent_path(&path)),
+            index
+        ));
+    }
+    script.join("; ")
+}
+
+/// Ask the daemon for its modules and make sure the node's module is one of them.
+fn probe_daemon(node: &eriksync::Node, timeout: Duration, report: &mut Report) {
+    let mut command = std::process::Command::new("rsync");
+    command.arg("--list-only").arg(format!(
+        "--contimeout={}",
+        timeout.as_secs()
+    ));
+    if let Some(ref password_file) = node.password_file {
+        let password_file = utils::expand_user(std::path::Path::new(password_file));
+        command.arg(format!(
+            "--password-file={}",
+            password_file.to_str().unwrap_or_default()
+        ));
+    }
+    let url = rsync_command::daemon_url(node, "");
+    let root = &url[..url.rfind('/').unwrap_or(url.len()) + 1];
+    command.arg(root);
+
+    let output = match utils::output_with_timeout(&mut command, timeout * 2) {
+        Ok(output) => output,
+        Err(e) => {
+            report.add("connect", Status::Fail, e);
+            return;
+        }
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().next().unwrap_or("no response").trim().to_string();
+        report.add("connect", Status::Fail, reason);
+        return;
+    }
+    report.add("connect", Status::Pass, format!("connected to {}", root));
+
+    let module = node.module.clone().unwrap_or_default();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.lines().any(|line| line.split_whitespace().next() == Some(module.as_str())) {
+        report.add("module", Status::Pass, format!("{} is available", module));
+    } else {
+        report.add("module", Status::Fail, format!("{} is not listed", module));
+    }
+}
+
+/// Pick `3.2.7 (protocol 31)` out of the first line of `rsync --version`.
+fn rsync_version(line: &str) -> Option<String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let version = words.iter().position(|w| *w == "version").and_then(
+        |i| words.get(i + 1),
+    )?;
+    match words.iter().rposition(|w| *w == "version") {
+        Some(i) if i > 0 && words[i - 1] == "protocol" => {
+            Some(format!("{} (protocol {})", version, words.get(i + 1).unwrap_or(&"?")))
+        }
+        _ => Some(version.to_string()),
+    }
+}
+
+fn parent_path(path: &str) -> String {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(0) => String::from("/"),
+        Some(i) => path[..i].to_string(),
+        None if path.starts_with('~') => path.to_string(),
+        None => String::from("."),
+    }
+}
+
+/// Quote a path for the remote shell, keeping a leading `~` or `~user` expandable.
+fn shell_path(path: &str) -> String {
+    if path == "~" {
+        String::from("\"$HOME\"")
+    } else if let Some(rest) = path.strip_prefix("~/") {
+        format!("\"$HOME\"/{}", utils::shell_quote(rest))
+    } else if path.starts_with('~') &&
+               path.chars().skip(1).take_while(|&c| c != '/').all(
+            |c| c.is_ascii_alphanumeric() || "-_.".contains(c),
+        )
+    {
+        match path.find('/') {
+            Some(i) => format!("{}/{}", &path[..i], utils::shell_quote(&path[i + 1..])),
+            None => path.to_string(),
+        }
+    } else {
+        utils::shell_quote(path)
+    }
+}
+
+fn format_kb(kb: u64) -> String {
+    if kb >= 1024 * 1024 {
+        format!("{:.1} GiB", kb as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} MiB", kb as f64 / 1024.0)
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
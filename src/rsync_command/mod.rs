@@ -3,96 +3,771 @@ use eriksync;
 use utils;
 
 pub struct RsyncCommand {
+    target: String,
+    /// Where the remote path of the target comes from.
+    path_note: String,
+    direction: Direction,
     command: String,
     arguements: Vec<String>,
+    /// Command that has to succeed before remote sudo is relied on. It runs the same
+    /// rsync under `sudo -n`, so sudoers rules limited to rsync are honored.
+    sudo_check: Option<Vec<String>>,
+    pre_hooks: Vec<eriksync::Hook>,
+    post_hooks: Vec<eriksync::Hook>,
+    /// SSH command line reaching the node, used by remote hooks.
+    ssh: Option<Vec<String>>,
+    /// Environment describing the transfer to hooks.
+    hook_env: Vec<(String, String)>,
+}
+
+pub use eriksync::Direction;
+
+impl RsyncCommand {
+    pub fn target(&self) -> &str {
+        &self.target
+    }
 }
 
-pub enum Direction {
-    Push,
-    Pull,
+/// Print where the remote path of every command's target comes from.
+pub fn show_paths(commands: &[RsyncCommand]) {
+    for cmd in commands {
+        println!("# {}", cmd.path_note);
+    }
 }
 
 pub fn show_commands(commands: &Vec<RsyncCommand>) {
     for cmd in commands {
+        for hook in &cmd.pre_hooks {
+            show_hook(cmd, "pre", hook);
+        }
         println!("{} {:?}", cmd.command, cmd.arguements);
+        for hook in &cmd.post_hooks {
+            show_hook(cmd, "post", hook);
+        }
     }
 }
 
-pub fn run_commands(commands: &Vec<RsyncCommand>) {
+fn show_hook(cmd: &RsyncCommand, stage: &str, hook: &eriksync::Hook) {
+    println!(
+        "{}_{} hook ({}): {}",
+        stage,
+        cmd.direction,
+        if hook.remote { "remote" } else { "local" },
+        hook.command
+    );
+}
+
+/// Run a hook with the transfer described in its environment. `status` is the exit
+/// status of rsync for hooks run after it.
+fn run_hook(
+    cmd: &RsyncCommand,
+    stage: &str,
+    hook: &eriksync::Hook,
+    status: Option<i32>,
+) -> Result<(), String> {
+    let mut env = cmd.hook_env.clone();
+    if let Some(status) = status {
+        env.push((String::from("ERIKSYNC_STATUS"), status.to_string()));
+    }
+
+    let mut command = match (hook.remote, &cmd.ssh) {
+        (true, Some(ssh)) => {
+            let assignments: Vec<String> = env.iter()
+                .map(|(k, v)| format!("{}={}", k, utils::shell_quote(v)))
+                .collect();
+            let mut command = std::process::Command::new(&ssh[0]);
+            command.args(&ssh[1..]).arg(format!(
+                "env {} sh -c {}",
+                assignments.join(" "),
+                utils::shell_quote(&hook.command)
+            ));
+            command
+        }
+        _ => {
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(&hook.command).envs(env);
+            command
+        }
+    };
+
+    let label = format!("{}_{} hook of target {}", stage, cmd.direction, cmd.target);
+    let hook_status = command.status().map_err(
+        |e| format!("Failed to execute {}: {}", label, e),
+    )?;
+    if !hook_status.success() {
+        return Err(format!("{} exited with {}", label, hook_status));
+    }
+    Ok(())
+}
+
+/// Run the commands in order. A failing pre hook or a refused privileged target
+/// skips only its own target; the remaining targets still run and the failures
+/// are returned at the end.
+pub fn run_commands(commands: &Vec<RsyncCommand>) -> Result<(), String> {
     show_commands(commands);
+    let mut failures: Vec<String> = Vec::new();
     for cmd in commands {
-        let mut child = std::process::Command::new(cmd.command.clone())
+        // Checked before the pre hooks so a refused target has no side effects.
+        if let Err(e) = check_sudo(cmd) {
+            errln!("{}", e);
+            failures.push(e);
+            continue;
+        }
+
+        let pre_hooks = cmd.pre_hooks.iter().try_for_each(
+            |hook| run_hook(cmd, "pre", hook, None),
+        );
+        if let Err(e) = pre_hooks {
+            errln!("Skipping target {}: {}", cmd.target, e);
+            failures.push(e);
+            continue;
+        }
+
+        let status = std::process::Command::new(cmd.command.clone())
             .args(cmd.arguements.as_slice())
-            .spawn()
-            .expect("Failed to execute child.");
+            .status()
+            .map_err(|e| format!("Failed to execute {}: {}", cmd.command, e))?;
 
-        let exit_code = child.wait().expect("failed to wait on child");
-        if !exit_code.success() {
-            break;
+        let hook_result = cmd.post_hooks.iter().try_for_each(|hook| {
+            run_hook(cmd, "post", hook, status.code())
+        });
+        if !status.success() {
+            return Err(format!("{} exited with {}", cmd.command, status));
         }
+        hook_result?;
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join("; "))
+    }
+}
+
+/// Make sure sudo works without a password on the node of a privileged target.
+fn check_sudo(cmd: &RsyncCommand) -> Result<(), String> {
+    let sudo_check = match cmd.sudo_check {
+        Some(ref sudo_check) => sudo_check,
+        None => return Ok(()),
+    };
+    let output = std::process::Command::new(&sudo_check[0])
+        .args(&sudo_check[1..])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to execute {}: {}", sudo_check[0], e))?;
+    // ssh exits with 255 when it cannot reach the node at all.
+    if output.status.code() == Some(255) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "Failed to connect for privileged target {}: {}",
+            cmd.target,
+            stderr.lines().last().unwrap_or("ssh failed").trim()
+        ));
+    }
+    if !output.status.success() {
+        return Err(format!(
+            "Refusing to sync privileged target {}: sudo on the node is unavailable or needs a password",
+            cmd.target
+        ));
     }
+    Ok(())
 }
 
 pub fn generate_commands(
     config: &eriksync::Config,
     node_name: &String,
-    target_list: &Vec<String>,
+    target_list: &[String],
     direction: Direction,
-) -> Vec<RsyncCommand> {
+    force: bool,
+) -> Result<Vec<RsyncCommand>, String> {
+
+    let node = match config.get_node(node_name) {
+        Some(node) => node,
+        None => return Err(format!("No such node: {}", node_name)),
+    };
 
-    if !config.contains_node(node_name) || target_list.is_empty() {
-        return Vec::new();
+    let targets = select_targets(config, node, target_list)?;
+
+    if !force {
+        if direction == Direction::Push && node.read_only {
+            return Err(format!(
+                "Node {} is read-only, refusing to push (use --force to override)",
+                node_name
+            ));
+        }
+        for target in targets.iter().filter_map(|t| config.get_target(t)) {
+            if !target.allows_direction(direction) {
+                return Err(format!(
+                    "Target {} does not allow {} (use --force to override)",
+                    target.name,
+                    direction
+                ));
+            }
+        }
     }
 
-    let targets = if target_list[0].to_lowercase() == "all" {
-        config.target_names()
-    } else {
-        target_list.to_vec()
-    };
+    let node = &node.expanded()?;
+    check_node(node)?;
 
-    let command_getter = match direction {
-        Direction::Push => push_command,
-        Direction::Pull => pull_command,
-    };
+    if node.kind != eriksync::NodeKind::Ssh {
+        for target in targets.iter().filter_map(|t| config.get_target(t)) {
+            if target.privileged {
+                return Err(format!(
+                    "Node {} cannot keep ownership for privileged target {} without SSH",
+                    node_name,
+                    target.name
+                ));
+            }
+        }
+
+        let has_remote_hooks = |hooks: &eriksync::config::Hooks| {
+            hooks.pre(direction).iter().chain(hooks.post(direction)).any(
+                |h| h.remote,
+            )
+        };
+        if has_remote_hooks(&config.hooks) {
+            return Err(format!("Node {} cannot run remote hooks without SSH", node_name));
+        }
+        for target in targets.iter().filter_map(|t| config.get_target(t)) {
+            if has_remote_hooks(&target.hooks) {
+                return Err(format!(
+                    "Node {} cannot run remote hooks of target {} without SSH",
+                    node_name,
+                    target.name
+                ));
+            }
+        }
+    }
 
-    let node = config.get_node(node_name).expect("No such node");
     targets
         .iter()
         .filter(|t| config.contains_target(t))
-        .map(|t| command_getter(node, config.get_target(t).unwrap()))
+        .map(|t| {
+            let target = config.get_target(t).unwrap().expanded(&node.name)?;
+            Ok(match direction {
+                Direction::Push => push_command(config, node, &target),
+                Direction::Pull => pull_command(config, node, &target),
+            })
+        })
+        .collect()
+}
+
+/// Names of the targets the node accepts, sorted.
+pub fn allowed_targets(config: &eriksync::Config, node: &eriksync::Node) -> Vec<String> {
+    config
+        .targets()
+        .into_iter()
+        .filter(|t| node.allows_target(t))
+        .map(|t| t.name)
         .collect()
 }
 
-pub fn push_command(node: &eriksync::Node, target: &eriksync::Target) -> RsyncCommand {
+/// Expand target selectors into target names.
+///
+/// A selector is a target name, `@tag` or `all`; prefixed with `!` it removes the
+/// matching targets instead. Without any selector the node's default targets are
+/// used, and with only removals every target the node accepts is the base set.
+/// Tags and `all` only pick targets the node accepts, while naming a target the
+/// node does not accept is an error.
+pub fn select_targets(
+    config: &eriksync::Config,
+    node: &eriksync::Node,
+    selectors: &[String],
+) -> Result<Vec<String>, String> {
+    if selectors.is_empty() {
+        if node.targets.is_empty() {
+            return Err(format!(
+                "No targets given and node {} has no default targets",
+                node.name
+            ));
+        }
+        return Ok(allowed_targets(config, node));
+    }
+
+    let allowed = allowed_targets(config, node);
+    let mut selected: Vec<String> = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    let mut has_additions = false;
+    for selector in selectors {
+        let (remove, selector) = match selector.strip_prefix('!') {
+            Some(selector) => (true, selector),
+            None => (false, selector.as_str()),
+        };
+
+        let names = if selector.to_lowercase() == "all" {
+            allowed.clone()
+        } else if let Some(tag) = selector.strip_prefix('@') {
+            if !config.targets().iter().any(|t| t.has_tag(tag)) {
+                return Err(format!("No target is tagged @{}", tag));
+            }
+            allowed
+                .iter()
+                .filter(|t| config.get_target(t).unwrap().has_tag(tag))
+                .cloned()
+                .collect()
+        } else {
+            let name = selector.to_string();
+            if !config.contains_target(&name) {
+                return Err(format!("No such target: {}", name));
+            }
+            if !remove && !allowed.contains(&name) {
+                return Err(format!(
+                    "Target {} is not allowed on node {}",
+                    name,
+                    node.name
+                ));
+            }
+            vec![name]
+        };
+
+        if remove {
+            removed.extend(names);
+        } else {
+            has_additions = true;
+            for name in names {
+                if !selected.contains(&name) {
+                    selected.push(name);
+                }
+            }
+        }
+    }
+
+    if !has_additions {
+        selected = allowed;
+    }
+    selected.retain(|t| !removed.contains(t));
+    Ok(selected)
+}
+
+/// Make sure the node can be synchronized with before any command is generated for it.
+pub fn check_node(node: &eriksync::Node) -> Result<(), String> {
+    match node.kind {
+        eriksync::NodeKind::Ssh => Ok(()),
+        eriksync::NodeKind::Local => {
+            let mount_point = match node.mount_point {
+                Some(ref mount_point) => utils::expand_user(std::path::Path::new(mount_point)),
+                None => {
+                    return Err(format!("Skipping node {}: no mount_point configured", node.name));
+                }
+            };
+            if !mount_point.is_dir() {
+                Err(format!(
+                    "Skipping node {}: mount point {:?} does not exist",
+                    node.name,
+                    mount_point
+                ))
+            } else if !utils::is_mount_point(&mount_point) {
+                Err(format!(
+                    "Skipping node {}: nothing is mounted on {:?}",
+                    node.name,
+                    mount_point
+                ))
+            } else {
+                Ok(())
+            }
+        }
+        eriksync::NodeKind::Daemon => {
+            if node.module.is_none() {
+                return Err(format!("Skipping node {}: no module configured", node.name));
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn push_command(
+    config: &eriksync::Config,
+    node: &eriksync::Node,
+    target: &eriksync::Target,
+) -> RsyncCommand {
     let (local_dir, remote_dir) = get_target_pair(node, target);
-    get_command(local_dir, remote_dir)
+    let sources = if target.paths.is_empty() {
+        vec![local_dir]
+    } else {
+        expand_sub_paths(&local_dir, &target.paths)
+            .iter()
+            .map(|p| relative_source(&local_dir, p))
+            .collect()
+    };
+    get_command(config, node, target, Direction::Push, sources, remote_dir)
 }
 
-pub fn pull_command(node: &eriksync::Node, target: &eriksync::Target) -> RsyncCommand {
+pub fn pull_command(
+    config: &eriksync::Config,
+    node: &eriksync::Node,
+    target: &eriksync::Target,
+) -> RsyncCommand {
     let (local_dir, remote_dir) = get_target_pair(node, target);
-    get_command(remote_dir, local_dir)
+    let sources = if target.paths.is_empty() {
+        vec![remote_dir]
+    } else {
+        // Wildcards are left for the remote rsync to expand, but a local node has
+        // none and rsync is spawned without a shell, so expand them here.
+        let paths = match node.kind {
+            eriksync::NodeKind::Local => expand_sub_paths(&remote_dir, &target.paths),
+            _ => target.paths.clone(),
+        };
+        paths.iter().map(|p| relative_source(&remote_dir, p)).collect()
+    };
+    get_command(config, node, target, Direction::Pull, sources, local_dir)
 }
 
-fn get_command(src: String, dest: String) -> RsyncCommand {
+/// Source argument that `--relative` recreates below the destination starting at `path`.
+fn relative_source(root: &str, path: &str) -> String {
+    format!("{}/./{}", root.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+/// Expand wildcards of the paths of a multi-path target against a root on this host.
+/// Patterns matching nothing are kept so rsync reports them.
+fn expand_sub_paths(root: &str, paths: &[String]) -> Vec<String> {
+    let root = std::path::Path::new(root);
+    let mut expanded = Vec::new();
+    for path in paths {
+        let path = path.trim_start_matches('/');
+        let matches: Vec<String> = if utils::has_wildcards(path) {
+            utils::glob(&root.join(path))
+                .iter()
+                .filter_map(|m| m.strip_prefix(root).ok())
+                .map(|m| String::from(m.to_str().unwrap_or_default()))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if matches.is_empty() {
+            expanded.push(path.to_string());
+        } else {
+            expanded.extend(matches);
+        }
+    }
+    expanded
+}
+
+fn get_command(
+    config: &eriksync::Config,
+    node: &eriksync::Node,
+    target: &eriksync::Target,
+    direction: Direction,
+    sources: Vec<String>,
+    dest: String,
+) -> RsyncCommand {
+    let mut arguements = vec![String::from("-avzHSP")];
+    if !target.paths.is_empty() {
+        arguements.push(String::from("--relative"));
+    }
+    match target.mode_for(direction) {
+        eriksync::SyncMode::Mirror => arguements.push(String::from("--delete")),
+        eriksync::SyncMode::Update => {
+            arguements.push(String::from("--delete"));
+            arguements.push(String::from("--update"));
+        }
+        eriksync::SyncMode::Additive => {}
+        eriksync::SyncMode::IgnoreExisting => {
+            arguements.push(String::from("--delete"));
+            arguements.push(String::from("--ignore-existing"));
+        }
+    }
+    if target.privileged {
+        arguements.push(String::from("-o"));
+        arguements.push(String::from("-g"));
+        arguements.push(String::from("--numeric-ids"));
+    }
+    arguements.extend(filter_arguments(config, target));
+
+    let mut sudo_check = None;
+    match node.kind {
+        eriksync::NodeKind::Ssh => {
+            arguements.push(String::from("-e"));
+            arguements.push(ssh_transport(node));
+
+            let rsync_path = node.rsync_path.clone().unwrap_or_else(|| String::from("rsync"));
+            if target.privileged {
+                arguements.push(format!("--rsync-path=sudo -n {}", rsync_path));
+
+                let mut check = ssh_args(node);
+                check.push(String::from("-o"));
+                check.push(String::from("BatchMode=yes"));
+                check.push(node.destination());
+                check.push(format!("sudo -n {} --version", rsync_path));
+                sudo_check = Some(check);
+            } else if node.rsync_path.is_some() {
+                arguements.push(format!("--rsync-path={}", rsync_path));
+            }
+        }
+        eriksync::NodeKind::Daemon => {
+            if let Some(ref password_file) = node.password_file {
+                let password_file = utils::expand_user(std::path::Path::new(password_file));
+                arguements.push(format!(
+                    "--password-file={}",
+                    password_file.to_str().unwrap_or_default()
+                ));
+            }
+        }
+        eriksync::NodeKind::Local => {}
+    }
+    arguements.extend(sources);
+    arguements.push(dest);
+
+    let command = if target.local_sudo {
+        arguements.insert(0, String::from("rsync"));
+        String::from("sudo")
+    } else {
+        String::from("rsync")
+    };
+
+    let ssh = if node.kind == eriksync::NodeKind::Ssh {
+        let mut ssh = ssh_args(node);
+        ssh.push(node.destination());
+        Some(ssh)
+    } else {
+        None
+    };
+    let local_path = utils::expand_user(std::path::Path::new(target.get_local_path()));
+    let hook_env = vec![
+        (String::from("ERIKSYNC_NODE"), node.name.clone()),
+        (String::from("ERIKSYNC_TARGET"), target.name.clone()),
+        (String::from("ERIKSYNC_DIRECTION"), direction.to_string()),
+        (
+            String::from("ERIKSYNC_LOCAL_PATH"),
+            String::from(local_path.to_str().unwrap_or_default()),
+        ),
+        (String::from("ERIKSYNC_REMOTE_PATH"), remote_path(node, target)),
+    ];
+
     RsyncCommand {
-        command: "rsync".to_string(),
-        arguements: vec![
-            String::from("-avzHSP"),
-            String::from("--delete"),
-            String::from("-e"),
-            String::from("ssh"),
-            src,
-            dest,
-        ],
+        target: target.name.clone(),
+        path_note: explain_remote_path(node, target),
+        direction,
+        command,
+        arguements,
+        sudo_check,
+        pre_hooks: config
+            .hooks
+            .pre(direction)
+            .iter()
+            .chain(target.hooks.pre(direction))
+            .cloned()
+            .collect(),
+        post_hooks: target
+            .hooks
+            .post(direction)
+            .iter()
+            .chain(config.hooks.post(direction))
+            .cloned()
+            .collect(),
+        ssh,
+        hook_env,
+    }
+}
+
+/// Name of the per-directory file holding exclude patterns of a target.
+const IGNORE_FILE: &str = ".eriksyncignore";
+
+/// `--filter` arguments of a target, in the order rsync should try them: raw filter
+/// rules, includes, the ignore file, the target's excludes and the global excludes.
+fn filter_arguments(config: &eriksync::Config, target: &eriksync::Target) -> Vec<String> {
+    let mut arguements: Vec<String> = target
+        .filter
+        .iter()
+        .map(|rule| format!("--filter={}", rule))
+        .collect();
+    arguements.extend(target.include.iter().map(
+        |pattern| format!("--filter=+ {}", pattern),
+    ));
+    arguements.push(format!("--filter=:- {}", IGNORE_FILE));
+    arguements.extend(
+        target
+            .exclude
+            .iter()
+            .chain(config.excludes.iter())
+            .map(|pattern| format!("--filter=- {}", pattern)),
+    );
+    arguements
+}
+
+/// Command line used to open an SSH connection to the node, without the destination.
+pub fn ssh_args(node: &eriksync::Node) -> Vec<String> {
+    let mut args = vec![node.ssh_program.clone().unwrap_or_else(|| String::from("ssh"))];
+    if let Some(port) = node.port {
+        args.push(String::from("-p"));
+        args.push(port.to_string());
+    }
+    if let Some(ref identity_file) = node.identity_file {
+        args.push(String::from("-i"));
+        args.push(identity_file.clone());
+    }
+    if !node.proxy_jump.is_empty() {
+        args.push(String::from("-J"));
+        args.push(node.proxy_jump.join(","));
     }
+    for option in &node.ssh_options {
+        args.push(String::from("-o"));
+        args.push(option.clone());
+    }
+    args
+}
+
+/// The `-e` argument of rsync, quoted the way rsync splits its remote shell command.
+fn ssh_transport(node: &eriksync::Node) -> String {
+    ssh_args(node)
+        .iter()
+        .map(|arg| quote_transport_arg(arg))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn quote_transport_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"') {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "''"))
 }
 
 fn get_target_pair(node: &eriksync::Node, target: &eriksync::Target) -> (String, String) {
-    let remote_dir = format!("{}:{}", node.name, target.path);
-    let local_dir = utils::expand_user(&std::path::Path::new(&target.path));
+    let remote_dir = match node.kind {
+        eriksync::NodeKind::Ssh => format!("{}:{}", node.destination(), remote_path(node, target)),
+        eriksync::NodeKind::Local => remote_path(node, target),
+        eriksync::NodeKind::Daemon => daemon_url(node, &remote_path(node, target)),
+    };
+    let local_dir = utils::expand_user(std::path::Path::new(target.get_local_path()));
 
     (
-        String::from(local_dir.to_str().unwrap_or_default()),
-        remote_dir,
+        target.rsync_path(local_dir.to_str().unwrap_or_default()),
+        target.rsync_path(&remote_dir),
     )
 }
+
+/// Path of a target on the node: as seen by the remote shell on SSH nodes, under the
+/// mount point on local nodes and relative to the module on daemon nodes.
+pub fn remote_path(node: &eriksync::Node, target: &eriksync::Target) -> String {
+    let path = node.remote_path(target.get_remote_path_for(&node.name));
+    let relative = path.trim_start_matches('~').trim_start_matches('/');
+    match node.kind {
+        eriksync::NodeKind::Ssh => path.clone(),
+        eriksync::NodeKind::Local => {
+            let mount_point = utils::expand_user(std::path::Path::new(
+                node.mount_point.as_ref().map_or("", |m| m.as_str()),
+            ));
+            String::from(mount_point.join(relative).to_str().unwrap_or_default())
+        }
+        eriksync::NodeKind::Daemon => relative.to_string(),
+    }
+}
+
+/// Explain where the path of a target on a node comes from.
+pub fn explain_remote_path(node: &eriksync::Node, target: &eriksync::Target) -> String {
+    let path = target.get_remote_path_for(&node.name);
+    let mut explanation = format!(
+        "{}: {} from {}",
+        target.name,
+        path,
+        target.remote_path_source(&node.name)
+    );
+    if let Some((rewritten, rule)) = node.rewrite_path(path) {
+        explanation.push_str(&format!(
+            ", rewritten to {} by {} of node {}",
+            rewritten,
+            rule,
+            node.name
+        ));
+    }
+    explanation
+}
+
+/// `rsync://[user@]host[:port]/module/path` URL of a path inside the module of a daemon node.
+pub fn daemon_url(node: &eriksync::Node, path: &str) -> String {
+    let mut url = String::from("rsync://");
+    if let Some(ref user) = node.user {
+        url.push_str(&format!("{}@", user));
+    }
+    url.push_str(node.hostname());
+    if let Some(port) = node.port {
+        url.push_str(&format!(":{}", port));
+    }
+    url.push('/');
+    url.push_str(node.module.as_ref().map_or("", |m| m.trim_matches('/')));
+
+    if !path.is_empty() {
+        url.push('/');
+        url.push_str(path);
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use eriksync;
+    use super::select_targets;
+
+    fn config() -> eriksync::Config {
+        let mut config = eriksync::Config::new();
+        config.add_target(
+            eriksync::Target::new(String::from("docs"), String::from("~/docs"))
+                .tag(String::from("work")),
+        );
+        config.add_target(
+            eriksync::Target::new(String::from("music"), String::from("~/Music"))
+                .tag(String::from("media")),
+        );
+        config.add_target(
+            eriksync::Target::new(String::from("photos"), String::from("~/Photos"))
+                .tag(String::from("media")),
+        );
+        config.add_target(eriksync::Target::new(
+            String::from("secrets"),
+            String::from("~/.secrets"),
+        ));
+        config
+    }
+
+    fn select(node: &eriksync::Node, selectors: &[&str]) -> Result<Vec<String>, String> {
+        let selectors: Vec<String> = selectors.iter().map(|s| s.to_string()).collect();
+        select_targets(&config(), node, &selectors)
+    }
+
+    fn names(names: &[&str]) -> Result<Vec<String>, String> {
+        Ok(names.iter().map(|n| n.to_string()).collect())
+    }
+
+    #[test]
+    fn union_of_names_and_tags_in_order_without_duplicates() {
+        let node = eriksync::Node::new(String::from("nas"));
+        assert_eq!(select(&node, &["docs", "@media", "music"]), names(&["docs", "music", "photos"]));
+    }
+
+    #[test]
+    fn removals_apply_after_additions() {
+        let node = eriksync::Node::new(String::from("nas"));
+        assert_eq!(select(&node, &["!music", "@media"]), names(&["photos"]));
+        assert_eq!(select(&node, &["all", "!@media"]), names(&["docs", "secrets"]));
+    }
+
+    #[test]
+    fn only_removals_start_from_every_allowed_target() {
+        let node = eriksync::Node::new(String::from("nas"));
+        assert_eq!(select(&node, &["!secrets"]), names(&["docs", "music", "photos"]));
+    }
+
+    #[test]
+    fn tags_and_all_skip_targets_the_node_does_not_accept() {
+        let node = eriksync::Node::new(String::from("nas")).target(String::from("!photos"));
+        assert_eq!(select(&node, &["@media"]), names(&["music"]));
+        assert_eq!(select(&node, &["all"]), names(&["docs", "music", "secrets"]));
+        assert!(select(&node, &["photos"]).is_err());
+    }
+
+    #[test]
+    fn no_selectors_use_node_defaults() {
+        let node = eriksync::Node::new(String::from("nas"));
+        assert!(select(&node, &[]).is_err());
+
+        let node = node.target(String::from("@work")).target(String::from("secrets"));
+        assert_eq!(select(&node, &[]), names(&["docs", "secrets"]));
+    }
+
+    #[test]
+    fn unknown_names_and_tags_are_errors() {
+        let node = eriksync::Node::new(String::from("nas"));
+        assert!(select(&node, &["nope"]).is_err());
+        assert!(select(&node, &["@nope"]).is_err());
+        assert!(select(&node, &["!nope"]).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use std;
+use std::io::prelude::*;
+use eriksync;
+use utils;
+
+/// A `Host` block of an OpenSSH client configuration.
+struct HostBlock {
+    patterns: Vec<String>,
+    options: Vec<(String, Vec<String>)>,
+}
+
+impl HostBlock {
+    fn new(patterns: Vec<String>) -> HostBlock {
+        HostBlock {
+            patterns,
+            options: Vec::new(),
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        let mut matched = false;
+        for pattern in &self.patterns {
+            if let Some(negated) = pattern.strip_prefix('!') {
+                if utils::wildcard_match(negated, host) {
+                    return false;
+                }
+            } else if utils::wildcard_match(pattern, host) {
+                matched = true;
+            }
+        }
+        matched
+    }
+}
+
+pub struct SshConfig {
+    blocks: Vec<HostBlock>,
+}
+
+impl SshConfig {
+    /// Parse an OpenSSH client configuration file, following `Include` directives.
+    pub fn load_file(path: &std::path::Path) -> Result<SshConfig, String> {
+        let mut config = SshConfig { blocks: Vec::new() };
+        config.parse_file(path, vec![String::from("*")], 0)?;
+        Ok(config)
+    }
+
+    fn parse_file(
+        &mut self,
+        path: &std::path::Path,
+        patterns: Vec<String>,
+        depth: usize,
+    ) -> Result<(), String> {
+        if depth > 16 {
+            return Err(format!("Too many nested includes at {:?}", path));
+        }
+
+        let mut content = String::new();
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+
+        let mut current = patterns;
+        self.blocks.push(HostBlock::new(current.clone()));
+        for line in content.lines() {
+            let (keyword, args) = match split_line(line) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+
+            match keyword.as_str() {
+                "host" => {
+                    current = args;
+                    self.blocks.push(HostBlock::new(current.clone()));
+                }
+                "match" => {
+                    // Match conditions cannot be evaluated here, so nothing in the block applies.
+                    current = Vec::new();
+                    self.blocks.push(HostBlock::new(Vec::new()));
+                }
+                "include" => {
+                    for arg in args {
+                        for include in utils::glob(&include_path(path, &arg)) {
+                            if include.is_file() {
+                                self.parse_file(&include, current.clone(), depth + 1)?;
+                            }
+                        }
+                    }
+                    self.blocks.push(HostBlock::new(current.clone()));
+                }
+                _ => {
+                    let block = self.blocks.last_mut().unwrap();
+                    block.options.push((keyword, args));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Host aliases declared without wildcards or negation, in file order.
+    pub fn hosts(&self) -> Vec<String> {
+        let mut hosts: Vec<String> = Vec::new();
+        for block in &self.blocks {
+            for pattern in &block.patterns {
+                if !pattern.starts_with('!') && !utils::has_wildcards(pattern) &&
+                    !hosts.contains(pattern)
+                {
+                    hosts.push(pattern.clone());
+                }
+            }
+        }
+        hosts
+    }
+
+    /// First value of an option that applies to the host, as ssh resolves it.
+    fn option(&self, host: &str, keyword: &str) -> Option<Vec<String>> {
+        self.blocks
+            .iter()
+            .filter(|block| block.matches(host))
+            .flat_map(|block| block.options.iter())
+            .find(|&(k, _)| k == keyword)
+            .map(|(_, args)| args.clone())
+    }
+
+    fn first_value(&self, host: &str, keyword: &str) -> Option<String> {
+        self.option(host, keyword).and_then(
+            |args| args.into_iter().next(),
+        )
+    }
+
+    /// Build a node for a host alias from the settings that apply to it.
+    pub fn node(&self, host: &str) -> eriksync::Node {
+        let mut node = eriksync::Node::new(host.to_string())
+            .description(String::from("Imported from ssh config"));
+
+        if let Some(hostname) = self.first_value(host, "hostname") {
+            let hostname = hostname.replace("%h", host);
+            if hostname != host {
+                node = node.host(hostname);
+            }
+        }
+        if let Some(user) = self.first_value(host, "user") {
+            node = node.user(user);
+        }
+        if let Some(port) = self.first_value(host, "port").and_then(|p| p.parse().ok()) {
+            if port != 22 {
+                node = node.port(port);
+            }
+        }
+        if let Some(identity_file) = self.first_value(host, "identityfile") {
+            node = node.identity_file(identity_file);
+        }
+        if let Some(proxy_jump) = self.first_value(host, "proxyjump") {
+            if proxy_jump.to_lowercase() != "none" {
+                for jump_host in proxy_jump.split(',') {
+                    node = node.proxy_jump(jump_host.to_string());
+                }
+            }
+        }
+        node
+    }
+}
+
+/// Default location of the user's ssh client configuration.
+pub fn default_path() -> std::path::PathBuf {
+    std::path::PathBuf::from(utils::home_dir()).join(".ssh").join("config")
+}
+
+/// Relative includes are resolved against `~/.ssh`, as ssh does for user configuration.
+fn include_path(from: &std::path::Path, include: &str) -> std::path::PathBuf {
+    let path = utils::expand_user(std::path::Path::new(include));
+    if path.is_absolute() {
+        return path;
+    }
+    let ssh_dir = std::path::PathBuf::from(utils::home_dir()).join(".ssh");
+    if ssh_dir.is_dir() {
+        ssh_dir.join(path)
+    } else {
+        from.parent().unwrap_or_else(|| std::path::Path::new("")).join(path)
+    }
+}
+
+/// Split a configuration line into its lowercased keyword and arguments.
+fn split_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let end = line.find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..end].to_lowercase();
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut quoted = false;
+    for c in rest.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !arg.is_empty() {
+                    args.push(arg.clone());
+                    arg.clear();
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        args.push(arg);
+    }
+
+    Some((keyword, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_line, HostBlock, SshConfig};
+
+    fn host_block(patterns: &[&str], options: &[(&str, &str)]) -> HostBlock {
+        let mut block = HostBlock::new(patterns.iter().map(|p| p.to_string()).collect());
+        for &(keyword, value) in options {
+            block.options.push((keyword.to_string(), vec![value.to_string()]));
+        }
+        block
+    }
+
+    #[test]
+    fn split_line_skips_blank_lines_and_comments() {
+        assert_eq!(split_line(""), None);
+        assert_eq!(split_line("   "), None);
+        assert_eq!(split_line("  # Host foo"), None);
+    }
+
+    #[test]
+    fn split_line_lowercases_keyword_and_accepts_equals() {
+        let expected = Some((String::from("hostname"), vec![String::from("example.com")]));
+        assert_eq!(split_line("HostName example.com"), expected);
+        assert_eq!(split_line("HostName=example.com"), expected);
+        assert_eq!(split_line("  HostName = example.com  "), expected);
+    }
+
+    #[test]
+    fn split_line_keeps_quoted_whitespace() {
+        assert_eq!(
+            split_line("IdentityFile \"~/.ssh/id work\" other"),
+            Some((
+                String::from("identityfile"),
+                vec![String::from("~/.ssh/id work"), String::from("other")],
+            ))
+        );
+    }
+
+    #[test]
+    fn host_block_matches_wildcards() {
+        let block = host_block(&["*.lan", "web?"], &[]);
+        assert!(block.matches("nas.lan"));
+        assert!(block.matches("web1"));
+        assert!(!block.matches("web10"));
+        assert!(!block.matches("example.com"));
+    }
+
+    #[test]
+    fn host_block_negation_wins_over_matches() {
+        let block = host_block(&["*.lan", "!bastion.lan"], &[]);
+        assert!(block.matches("nas.lan"));
+        assert!(!block.matches("bastion.lan"));
+        assert!(!host_block(&["!bastion.lan"], &[]).matches("nas.lan"));
+    }
+
+    #[test]
+    fn option_takes_first_matching_value() {
+        let config = SshConfig {
+            blocks: vec![
+                host_block(&["nas"], &[("user", "alice")]),
+                host_block(&["*"], &[("user", "root"), ("port", "2222")]),
+            ],
+        };
+        assert_eq!(config.first_value("nas", "user"), Some(String::from("alice")));
+        assert_eq!(config.first_value("nas", "port"), Some(String::from("2222")));
+        assert_eq!(config.first_value("web", "user"), Some(String::from("root")));
+        assert_eq!(config.first_value("web", "hostname"), None);
+    }
+
+    #[test]
+    fn option_skips_negated_blocks() {
+        let config = SshConfig {
+            blocks: vec![
+                host_block(&["*", "!nas"], &[("user", "root")]),
+                host_block(&["nas"], &[("user", "alice")]),
+            ],
+        };
+        assert_eq!(config.first_value("nas", "user"), Some(String::from("alice")));
+        assert_eq!(config.first_value("web", "user"), Some(String::from("root")));
+    }
+}
@@ -4,12 +4,349 @@ pub fn home_dir() -> String {
     String::from(std::env::var("HOME").unwrap_or_default())
 }
 
+/// Replace a leading `~` or `~user` with the home directory of the user.
 pub fn expand_user(path: &std::path::Path) -> std::path::PathBuf {
-    let mut path_str = String::from(path.to_str().unwrap_or_default());
+    let path_str = String::from(path.to_str().unwrap_or_default());
 
-    if path_str.len() > 0 && path_str.chars().nth(0).unwrap() == '~' {
-        path_str = path_str.replacen("~", &home_dir(), 1);
+    if let Some(rest) = path_str.strip_prefix('~') {
+        let end = rest.find('/').unwrap_or(rest.len());
+        let home = if end == 0 {
+            Some(home_dir())
+        } else {
+            user_home_dir(&rest[..end])
+        };
+        if let Some(home) = home {
+            return std::path::PathBuf::from(format!("{}{}", home, &rest[end..]));
+        }
     }
 
     std::path::PathBuf::from(&path_str)
 }
+
+/// Home directory of a user as listed in `/etc/passwd`.
+pub fn user_home_dir(user: &str) -> Option<String> {
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd_home_dir(&passwd, user)
+}
+
+fn passwd_home_dir(passwd: &str, user: &str) -> Option<String> {
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<&str>>())
+        .find(|fields| fields.len() > 5 && fields[0] == user)
+        .map(|fields| fields[5].to_string())
+}
+
+/// Name of the local machine.
+pub fn local_hostname() -> Option<String> {
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| {
+            std::process::Command::new("hostname")
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        })?;
+    let hostname = hostname.trim();
+    if hostname.is_empty() {
+        None
+    } else {
+        Some(hostname.to_string())
+    }
+}
+
+/// Today's date in UTC as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    date_from_unix(secs)
+}
+
+/// UTC date of a Unix timestamp as `YYYY-MM-DD`.
+fn date_from_unix(secs: u64) -> String {
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Expand `${VAR}` from the environment and `{name}` with `variable`.
+///
+/// `variable` returns `Ok(None)` for names it does not know, and such `{name}` is
+/// kept as it is, so paths that happen to contain braces keep working. `$$` gives a
+/// literal `$`, so `$${VAR}` gives `${VAR}`, and `{{` gives a literal `{`, so
+/// `{{node}` gives `{node}`. An undefined environment variable is an error.
+pub fn expand_template<F>(template: &str, variable: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Result<Option<String>, String>,
+{
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['$', '{']) {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if let Some(after) = rest.strip_prefix("$$") {
+            // `$${VAR}` is kept as a literal `${VAR}`.
+            let len = if after.starts_with('{') {
+                after.find('}').map_or(0, |end| end + 1)
+            } else {
+                0
+            };
+            expanded.push('$');
+            expanded.push_str(&after[..len]);
+            rest = &after[len..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("{{") {
+            expanded.push('{');
+            rest = after;
+            continue;
+        }
+
+        if rest.starts_with("${") {
+            let end = rest.find('}').ok_or_else(|| {
+                format!("unterminated variable in {}", template)
+            })?;
+            match std::env::var(&rest[2..end]) {
+                Ok(value) => expanded.push_str(&value),
+                Err(_) => {
+                    return Err(format!("undefined variable {} in {}", &rest[..end + 1], template))
+                }
+            }
+            rest = &rest[end + 1..];
+        } else if rest.starts_with('{') {
+            let end = match rest.find('}') {
+                Some(end) if is_variable_name(&rest[1..end]) => end,
+                _ => {
+                    expanded.push('{');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            let value = variable(&rest[1..end]).map_err(
+                |e| format!("{} in {}", e, template),
+            )?;
+            match value {
+                Some(value) => expanded.push_str(&value),
+                None => expanded.push_str(&rest[..end + 1]),
+            }
+            rest = &rest[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether `path` is a directory with a filesystem mounted on it.
+#[cfg(unix)]
+pub fn is_mount_point(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return false,
+    };
+    if !metadata.is_dir() {
+        return false;
+    }
+    let parent = match std::fs::metadata(path.join("..")) {
+        Ok(parent) => parent,
+        Err(_) => return false,
+    };
+    metadata.dev() != parent.dev() || metadata.ino() == parent.ino()
+}
+
+#[cfg(not(unix))]
+pub fn is_mount_point(path: &std::path::Path) -> bool {
+    path.is_dir()
+}
+
+/// Match `text` against a shell-style pattern where `*` matches any run of
+/// characters and `?` matches a single character.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Expand a path whose components may contain wildcards into the existing
+/// paths it matches, sorted. A path without wildcards is returned as is.
+pub fn glob(pattern: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let mut matches = vec![std::path::PathBuf::new()];
+    for component in pattern.components() {
+        let part = component.as_os_str().to_str().unwrap_or_default();
+        if !has_wildcards(part) {
+            for path in &mut matches {
+                path.push(component.as_os_str());
+            }
+            continue;
+        }
+
+        let mut expanded = Vec::new();
+        for dir in &matches {
+            let entries = match std::fs::read_dir(if dir.as_os_str().is_empty() {
+                std::path::Path::new(".")
+            } else {
+                dir.as_path()
+            }) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name();
+                let name = name.to_str().unwrap_or_default();
+                if name.starts_with('.') && !part.starts_with('.') {
+                    continue;
+                }
+                if wildcard_match(part, name) {
+                    expanded.push(dir.join(name));
+                }
+            }
+        }
+        matches = expanded;
+    }
+    matches.sort();
+    matches
+}
+
+/// Quote a string for a POSIX shell.
+pub fn shell_quote(s: &str) -> String {
+    if !s.is_empty() &&
+        s.chars().all(|c| {
+            c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c)
+        })
+    {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Run a command and collect its output, killing it when it takes longer than `timeout`.
+pub fn output_with_timeout(
+    command: &mut std::process::Command,
+    timeout: std::time::Duration,
+) -> Result<std::process::Output, String> {
+    let mut child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    let started = std::time::Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() >= timeout => {
+                child.kill().unwrap_or_default();
+                child.wait().map_err(|e| e.to_string())?;
+                return Err(format!("timed out after {}s", timeout.as_secs()));
+            }
+            Ok(None) => std::thread::sleep(std::time::Duration::from_millis(50)),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    child.wait_with_output().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{date_from_unix, expand_template, passwd_home_dir};
+
+    fn expand(template: &str) -> Result<String, String> {
+        expand_template(template, |name| match name {
+            "node" => Ok(Some(String::from("nas"))),
+            "target" => Err(String::from("no target")),
+            _ => Ok(None),
+        })
+    }
+
+    #[test]
+    fn expand_template_replaces_builtins_and_environment() {
+        std::env::set_var("ERIKSYNC_TEST_ROOT", "/srv");
+        assert_eq!(expand("${ERIKSYNC_TEST_ROOT}/{node}/docs"), Ok(String::from("/srv/nas/docs")));
+        assert_eq!(expand("/plain/path"), Ok(String::from("/plain/path")));
+    }
+
+    #[test]
+    fn expand_template_keeps_unknown_names_and_stray_braces() {
+        assert_eq!(expand("/a/{other}/{}/{a-b}/{"), Ok(String::from("/a/{other}/{}/{a-b}/{")));
+        assert_eq!(expand("/a/$x/$"), Ok(String::from("/a/$x/$")));
+    }
+
+    #[test]
+    fn expand_template_escapes() {
+        assert_eq!(expand("/a/$$/b"), Ok(String::from("/a/$/b")));
+        assert_eq!(expand("/a/$${HOME}"), Ok(String::from("/a/${HOME}")));
+        assert_eq!(expand("/a/$${node}"), Ok(String::from("/a/${node}")));
+        assert_eq!(expand("/a/{{node}/{node}"), Ok(String::from("/a/{node}/nas")));
+    }
+
+    #[test]
+    fn expand_template_errors() {
+        assert!(expand("${ERIKSYNC_TEST_UNDEFINED}/x").unwrap_err().contains(
+            "undefined variable ${ERIKSYNC_TEST_UNDEFINED}",
+        ));
+        assert!(expand("${HOME").unwrap_err().contains("unterminated"));
+        assert!(expand("/{target}").unwrap_err().contains("no target"));
+    }
+
+    #[test]
+    fn date_from_unix_gives_utc_dates() {
+        assert_eq!(date_from_unix(0), "1970-01-01");
+        assert_eq!(date_from_unix(951_782_400), "2000-02-29");
+        assert_eq!(date_from_unix(951_868_799), "2000-02-29");
+        assert_eq!(date_from_unix(1_704_067_199), "2023-12-31");
+        assert_eq!(date_from_unix(1_704_067_200), "2024-01-01");
+    }
+
+    #[test]
+    fn passwd_home_dir_finds_exact_user() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n\
+                      alice:x:1000:1000:Alice:/home/alice:/bin/sh\n\
+                      broken:x\n";
+        assert_eq!(passwd_home_dir(passwd, "alice"), Some(String::from("/home/alice")));
+        assert_eq!(passwd_home_dir(passwd, "ali"), None);
+        assert_eq!(passwd_home_dir(passwd, "broken"), None);
+    }
+}